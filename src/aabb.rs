@@ -0,0 +1,108 @@
+use crate::{interval::Interval, ray::Ray, vec3::{Point3, Vec3}};
+
+//axis-aligned bounding box stored as one interval per axis
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub x: Interval,
+    pub y: Interval,
+    pub z: Interval,
+}
+
+impl Aabb {
+    pub const EMPTY: Aabb = Aabb { x: Interval::EMPTY, y: Interval::EMPTY, z: Interval::EMPTY };
+
+    pub fn new(x: Interval, y: Interval, z: Interval) -> Self {
+        let mut bbox = Self { x, y, z };
+        bbox.pad_to_minimums();
+        bbox
+    }
+
+    //treat the two points as extrema of the box, in any order
+    pub fn from_points(a: Point3, b: Point3) -> Self {
+        Self::new(
+            Interval::new(a.x().min(b.x()), a.x().max(b.x())),
+            Interval::new(a.y().min(b.y()), a.y().max(b.y())),
+            Interval::new(a.z().min(b.z()), a.z().max(b.z())),
+        )
+    }
+
+    pub fn surrounding(a: &Aabb, b: &Aabb) -> Self {
+        Self {
+            x: Interval::enclosing(&a.x, &b.x),
+            y: Interval::enclosing(&a.y, &b.y),
+            z: Interval::enclosing(&a.z, &b.z),
+        }
+    }
+
+    pub fn axis_interval(&self, n: usize) -> Interval {
+        match n {
+            1 => self.y,
+            2 => self.z,
+            _ => self.x,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.min > self.x.max || self.y.min > self.y.max || self.z.min > self.z.max
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        Point3::new(
+            0.5*(self.x.min + self.x.max),
+            0.5*(self.y.min + self.y.max),
+            0.5*(self.z.min + self.z.max),
+        )
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        Vec3::new(self.x.size(), self.y.size(), self.z.size())
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0_f64;
+        }
+        let d = self.extent();
+        2_f64*(d.x()*d.y() + d.y()*d.z() + d.z()*d.x())
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let d = self.extent();
+        match (d.x() > d.y(), d.x() > d.z(), d.y() > d.z()) {
+            (true, true, _) => 0,
+            (_, _, true) => 1,
+            _ => 2,
+        }
+    }
+
+    pub fn hit(&self, ray: &Ray, ray_t: Interval) -> bool {
+        let inv_dir = Vec3::new(1_f64/ray.dir().x(), 1_f64/ray.dir().y(), 1_f64/ray.dir().z());
+        self.hit_with_inv_dir(&ray.origin(), &inv_dir, ray_t)
+    }
+
+    //slab test, with the reciprocal ray direction precomputed by the caller
+    pub fn hit_with_inv_dir(&self, origin: &Point3, inv_dir: &Vec3, mut ray_t: Interval) -> bool {
+        for axis in 0..3 {
+            let ax = self.axis_interval(axis);
+            let t0 = (ax.min - origin.get(axis))*inv_dir.get(axis);
+            let t1 = (ax.max - origin.get(axis))*inv_dir.get(axis);
+            let (t_near, t_far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+            //max/min written this way so a NaN slab (0 * inf) is ignored instead of poisoning the interval
+            if t_near > ray_t.min { ray_t.min = t_near; }
+            if t_far < ray_t.max { ray_t.max = t_far; }
+            if ray_t.max < ray_t.min {
+                return false;
+            }
+        }
+        true
+    }
+
+    //avoid degenerate boxes for flat primitives such as axis aligned triangles
+    fn pad_to_minimums(&mut self) {
+        let delta = 0.0001;
+        if self.x.size() < delta { self.x = self.x.expand(delta); }
+        if self.y.size() < delta { self.y = self.y.expand(delta); }
+        if self.z.size() < delta { self.z = self.z.expand(delta); }
+    }
+}
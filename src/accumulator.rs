@@ -0,0 +1,239 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::{color::Color, image::Image, sampler::SamplerKind};
+
+const MAGIC: &[u8; 8] = b"RTACCUM2";
+//magic, then width, height, seed and SamplerKind::id as u64
+const HEADER_BYTES: usize = 8 + 4*8;
+//sample count as u32, then the three f64 sums
+const PIXEL_BYTES: usize = 4 + 3*8;
+
+//running per-pixel sums of radiance samples, which progressive renders add to pass by pass.
+//can be saved to disk and loaded again to resume a render later
+pub struct Accumulator {
+    width: usize,
+    height: usize,
+    seed: u64,
+    sampler: SamplerKind,
+    sums: Vec<Color>,
+    samples: Vec<u32>,
+}
+
+impl Accumulator {
+    pub fn new(width: usize, height: usize, seed: u64, sampler: SamplerKind) -> Self {
+        Self {
+            width,
+            height,
+            seed,
+            sampler,
+            sums: vec![Color::new_empty(); width*height],
+            samples: vec![0; width*height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    //seed of the render that produced the samples, resumed renders should keep using it
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    //sampler the samples were drawn with, mixing in another one's would break up its patterns
+    pub fn sampler(&self) -> SamplerKind {
+        self.sampler
+    }
+
+    pub fn samples(&self, x: usize, y: usize) -> u32 {
+        self.samples[y*self.width + x]
+    }
+
+    pub fn min_samples(&self) -> u32 {
+        self.samples.iter().copied().min().unwrap_or(0)
+    }
+
+    pub fn add(&mut self, x: usize, y: usize, sum: Color, count: u32) {
+        let i = y*self.width + x;
+        self.sums[i] += sum;
+        self.samples[i] += count;
+    }
+
+    //average of the samples so far, black where a pixel has none yet
+    pub fn image(&self) -> Image {
+        let mut image = Image::new(self.width, self.height);
+        for (i, (sum, &count)) in self.sums.iter().zip(&self.samples).enumerate() {
+            if count > 0 {
+                image.set(i % self.width, i/self.width, *sum/count as f64);
+            }
+        }
+        image
+    }
+
+    //writes to a temporary file first so an interrupted save never clobbers the previous one
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+
+        let mut out = BufWriter::new(File::create(&tmp_path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&(self.width as u64).to_le_bytes())?;
+        out.write_all(&(self.height as u64).to_le_bytes())?;
+        out.write_all(&self.seed.to_le_bytes())?;
+        out.write_all(&self.sampler.id().to_le_bytes())?;
+        for (sum, &count) in self.sums.iter().zip(&self.samples) {
+            out.write_all(&count.to_le_bytes())?;
+            for c in [sum.x(), sum.y(), sum.z()] {
+                out.write_all(&c.to_le_bytes())?;
+            }
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp_path, path)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut input = BufReader::new(file);
+        let mut magic = [0_u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a render accumulation file"));
+        }
+
+        let mut u64_buf = [0_u8; 8];
+        let mut read_u64 = |input: &mut BufReader<File>| -> io::Result<u64> {
+            input.read_exact(&mut u64_buf)?;
+            Ok(u64::from_le_bytes(u64_buf))
+        };
+        let width = read_u64(&mut input)? as usize;
+        let height = read_u64(&mut input)? as usize;
+        let seed = read_u64(&mut input)?;
+        let sampler_id = read_u64(&mut input)?;
+        let sampler = SamplerKind::from_id(sampler_id)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("unknown sampler {} in accumulation file", sampler_id)))?;
+
+        //the header has to describe exactly the pixels that follow it, which also keeps a corrupt
+        //one from allocating an absurd amount of memory
+        let pixels = width.checked_mul(height).filter(|&pixels| pixels > 0);
+        let expected_len = pixels.and_then(|pixels| pixels.checked_mul(PIXEL_BYTES)).and_then(|len| len.checked_add(HEADER_BYTES));
+        if expected_len != Some(file_len as usize) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("corrupt accumulation file for a {}x{} image", width, height)));
+        }
+
+        let mut acc = Self::new(width, height, seed, sampler);
+        let mut count_buf = [0_u8; 4];
+        let mut f64_buf = [0_u8; 8];
+        for i in 0..width*height {
+            input.read_exact(&mut count_buf)?;
+            acc.samples[i] = u32::from_le_bytes(count_buf);
+            let mut c = [0_f64; 3];
+            for v in c.iter_mut() {
+                input.read_exact(&mut f64_buf)?;
+                *v = f64::from_le_bytes(f64_buf);
+            }
+            acc.sums[i] = Color::new(c[0], c[1], c[2]);
+        }
+        Ok(acc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempPath;
+
+    fn sample_accumulator() -> Accumulator {
+        let mut acc = Accumulator::new(3, 2, 0xdead_beef_1234, SamplerKind::Sobol);
+        acc.add(0, 0, Color::new(1.5, 0.25, -0.125), 3);
+        acc.add(2, 1, Color::new(1e300, f64::MIN_POSITIVE, 7_f64), 1);
+        acc.add(2, 1, Color::new(1_f64, 2_f64, 3_f64), 4);
+        acc
+    }
+
+    //saves `acc`, lets `corrupt` edit the file and loads it again
+    fn reload(name: &str, acc: &Accumulator, corrupt: impl FnOnce(&mut Vec<u8>)) -> io::Result<Accumulator> {
+        let path = TempPath::new(&format!("accumulator-{}", name));
+        acc.save(&path).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        corrupt(&mut bytes);
+        fs::write(&path, &bytes).unwrap();
+        Accumulator::load(&path)
+    }
+
+    fn assert_invalid_data(result: io::Result<Accumulator>) {
+        match result {
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{}", e),
+            Ok(acc) => panic!("expected invalid data, loaded a {}x{} accumulator", acc.width(), acc.height()),
+        }
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let acc = sample_accumulator();
+        let loaded = reload("round-trip", &acc, |_| {}).unwrap();
+        assert_eq!((loaded.width(), loaded.height(), loaded.seed(), loaded.sampler()), (3, 2, 0xdead_beef_1234, SamplerKind::Sobol));
+        for i in 0..6 {
+            let (x, y) = (i % 3, i/3);
+            assert_eq!(loaded.samples(x, y), acc.samples(x, y));
+            let (a, b) = (acc.sums[i], loaded.sums[i]);
+            assert_eq!([a.x(), a.y(), a.z()].map(f64::to_bits), [b.x(), b.y(), b.z()].map(f64::to_bits));
+        }
+        assert_eq!(loaded.min_samples(), 0);
+    }
+
+    #[test]
+    fn save_leaves_no_temporary_file() {
+        let path = TempPath::new("accumulator-no-tmp");
+        sample_accumulator().save(&path).unwrap();
+        let mut tmp_name = path.file_name().unwrap().to_os_string();
+        tmp_name.push(".tmp");
+        assert!(!path.with_file_name(tmp_name).exists());
+    }
+
+    #[test]
+    fn rejects_wrong_magic() {
+        assert_invalid_data(reload("magic", &sample_accumulator(), |bytes| bytes[0] = b'X'));
+    }
+
+    #[test]
+    fn rejects_truncated_and_padded_files() {
+        let acc = sample_accumulator();
+        assert_invalid_data(reload("truncated", &acc, |bytes| bytes.truncate(bytes.len() - 1)));
+        assert_invalid_data(reload("padded", &acc, |bytes| bytes.push(0)));
+    }
+
+    #[test]
+    fn rejects_corrupt_dimensions() {
+        let acc = sample_accumulator();
+        //a huge width has to fail on the length check instead of trying to allocate
+        assert_invalid_data(reload("huge", &acc, |bytes| bytes[8..16].copy_from_slice(&u64::MAX.to_le_bytes())));
+        assert_invalid_data(reload("zero", &acc, |bytes| bytes[16..24].copy_from_slice(&0_u64.to_le_bytes())));
+        //swapped dimensions describe the same number of pixels, so only the shape changes
+        let swapped = reload("swapped", &acc, |bytes| {
+            bytes[8..16].copy_from_slice(&2_u64.to_le_bytes());
+            bytes[16..24].copy_from_slice(&3_u64.to_le_bytes());
+        }).unwrap();
+        assert_eq!((swapped.width(), swapped.height()), (2, 3));
+    }
+
+    #[test]
+    fn rejects_unknown_sampler() {
+        assert_invalid_data(reload("sampler", &sample_accumulator(), |bytes| bytes[32..40].copy_from_slice(&99_u64.to_le_bytes())));
+    }
+
+    #[test]
+    fn rejects_truncated_header() {
+        let path = TempPath::new("accumulator-header");
+        fs::write(&path, &MAGIC[..5]).unwrap();
+        assert!(Accumulator::load(&path).is_err());
+    }
+}
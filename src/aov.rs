@@ -0,0 +1,165 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{color::Color, helper::{hash, hash_to_f64, INFINITY}, ray::{HitRecord, Ray}};
+
+//arbitrary output variables: properties of the first surface camera rays hit, rendered next to
+//the beauty image for compositing and denoising
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Aov {
+    Albedo, //material base color, averaged over the pixel with misses counting as black
+    Normal, //world space outward shading normal, averaged over the pixel
+    Position, //world space hit point, averaged over the samples that hit something
+    Depth, //distance from the camera, averaged over the samples that hit, infinite where all miss
+    ObjectId, //1 + index of the object in the world, 0 for background
+    MaterialId, //Scatter::id of the material, 0 for background
+    Coverage, //fraction of the pixel covered by geometry, usable as alpha
+}
+
+impl Aov {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Position => "position",
+            Aov::Depth => "depth",
+            Aov::ObjectId => "object",
+            Aov::MaterialId => "material",
+            Aov::Coverage => "coverage",
+        }
+    }
+
+    //ids can't be averaged, so they come from the first sample of the pixel only
+    pub fn is_id(&self) -> bool {
+        matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+
+    //whether the values are colors, rather than data that ldr formats should store unencoded
+    pub fn is_color(&self) -> bool {
+        matches!(self, Aov::Albedo)
+    }
+
+    //channel names in layered formats, one per component that carries data
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Depth => &["Z"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            Aov::Coverage => &["A"],
+        }
+    }
+
+    //raw value at a hit, the same in all three channels for single channel aovs
+    pub fn value(&self, ray: &Ray, hit_rec: &HitRecord) -> Color {
+        match self {
+            Aov::Albedo => hit_rec.mat.as_ref().map_or(Color::new_empty(), |mat| mat.albedo(hit_rec)),
+            Aov::Normal => if hit_rec.front_face { hit_rec.normal } else { -hit_rec.normal },
+            Aov::Position => hit_rec.p,
+            Aov::Depth => splat(hit_rec.t*ray.dir().get_len()),
+            Aov::ObjectId => splat((hit_rec.object_id + 1) as f64),
+            Aov::MaterialId => splat(hit_rec.mat.as_ref().map_or(0_f64, |mat| mat.id() as f64)),
+            Aov::Coverage => splat(1_f64),
+        }
+    }
+
+    //raw value where every sample missed
+    pub fn background(&self) -> Color {
+        match self {
+            Aov::Depth => splat(INFINITY),
+            _ => Color::new_empty(),
+        }
+    }
+
+    //maps a raw value into [0, 1] for viewing in ldr formats
+    pub fn display(&self, value: &Color) -> Color {
+        match self {
+            Aov::Albedo | Aov::Coverage => *value,
+            Aov::Normal => 0.5*(*value + splat(1_f64)),
+            Aov::Position => Color::new(fold(value.x()), fold(value.y()), fold(value.z())),
+            Aov::Depth => splat(1_f64/(1_f64 + value.x())),
+            Aov::ObjectId | Aov::MaterialId => match value.x() {
+                id if id <= 0_f64 => Color::new_empty(),
+                id => id_color(id as u64),
+            },
+        }
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "albedo" => Ok(Aov::Albedo),
+            "normal" | "normals" => Ok(Aov::Normal),
+            "position" => Ok(Aov::Position),
+            "depth" => Ok(Aov::Depth),
+            "object" | "object-id" => Ok(Aov::ObjectId),
+            "material" | "material-id" => Ok(Aov::MaterialId),
+            "coverage" | "alpha" => Ok(Aov::Coverage),
+            _ => Err(format!("unknown aov '{}', expected albedo, normal, position, depth, object, material or coverage", s)),
+        }
+    }
+}
+
+impl fmt::Display for Aov {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+//running sums for one pixel of one aov
+#[derive(Clone, Copy)]
+pub(crate) struct AovPixel {
+    sum: Color,
+    hits: u32,
+    samples: u32,
+    id: Option<Color>,
+}
+
+impl AovPixel {
+    pub(crate) fn new() -> Self {
+        Self { sum: Color::new_empty(), hits: 0, samples: 0, id: None }
+    }
+
+    pub(crate) fn add(&mut self, aov: Aov, ray: &Ray, hit_rec: Option<&HitRecord>) {
+        let value = hit_rec.map(|rec| aov.value(ray, rec));
+        if self.samples == 0 {
+            self.id = value;
+        }
+        self.samples += 1;
+        if let Some(value) = value {
+            self.sum += value;
+            self.hits += 1;
+        }
+    }
+
+    pub(crate) fn resolve(&self, aov: Aov) -> Color {
+        match aov {
+            _ if aov.is_id() => self.id.unwrap_or_else(|| aov.background()),
+            Aov::Albedo | Aov::Normal | Aov::Coverage if self.samples > 0 => self.sum/self.samples as f64,
+            _ if self.hits > 0 => self.sum/self.hits as f64,
+            _ => aov.background(),
+        }
+    }
+}
+
+//bright, well separated color for an id
+pub fn id_color(id: u64) -> Color {
+    let h = hash(&[id]);
+    Color::new(
+        0.2 + 0.8*hash_to_f64(h),
+        0.2 + 0.8*hash_to_f64(h.rotate_left(21)),
+        0.2 + 0.8*hash_to_f64(h.rotate_left(42)),
+    )
+}
+
+fn splat(v: f64) -> Color {
+    Color::new(v, v, v)
+}
+
+//repeating ramp so positions stay readable at any scene scale
+fn fold(v: f64) -> f64 {
+    v - v.floor()
+}
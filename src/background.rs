@@ -0,0 +1,109 @@
+use std::path::Path;
+
+use crate::{color::Color, colorspace::ColorSpace, helper::{deg_to_rad, PI}, image::{Image, WrapMode}, input::{read_hdr_image, ImageError}, vec3::{dot, unit_vector, Vec3}};
+
+//radiance arriving along rays that escape the scene
+pub trait Background: Send + Sync {
+    fn color(&self, dir: &Vec3) -> Color;
+}
+
+pub struct SolidBackground {
+    color: Color,
+}
+
+impl SolidBackground {
+    pub fn new(color: Color) -> Self {
+        Self {
+            color,
+        }
+    }
+}
+
+impl Background for SolidBackground {
+    fn color(&self, _: &Vec3) -> Color {
+        self.color
+    }
+}
+
+//lerps from `bottom` (looking straight along -up) to `top` (looking straight along up)
+pub struct GradientBackground {
+    bottom: Color,
+    top: Color,
+    up: Vec3,
+}
+
+impl GradientBackground {
+    pub fn new(bottom: Color, top: Color, up: Vec3) -> Self {
+        Self {
+            bottom,
+            top,
+            up: unit_vector(&up),
+        }
+    }
+
+    //the white to light blue sky the camera has always used
+    pub fn sky() -> Self {
+        Self::sky_in(ColorSpace::LinearSrgb)
+    }
+
+    //the same sky, with its srgb colors converted into the `working` space
+    pub fn sky_in(working: ColorSpace) -> Self {
+        let to_working = ColorSpace::LinearSrgb.conversion(working);
+        let bottom = to_working.transform(&Color::new(1_f64, 1_f64, 1_f64));
+        let top = to_working.transform(&Color::new(0.5_f64, 0.7_f64, 1_f64));
+        Self::new(bottom, top, Vec3::new(0_f64, 1_f64, 0_f64))
+    }
+}
+
+impl Background for GradientBackground {
+    fn color(&self, dir: &Vec3) -> Color {
+        let unit_dir : Vec3 = unit_vector(dir);
+        let a = 0.5_f64*(dot(&unit_dir, &self.up) + 1_f64);
+        (1_f64-a)*self.bottom + a*self.top
+    }
+}
+
+//equirectangular (latitude/longitude) environment map with +y up. the centre of the image
+//faces -z, `rotation` turns the map about the up axis in degrees
+pub struct EnvironmentMap {
+    image: Image,
+    pub rotation: f64,
+    pub intensity: f64,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: Image) -> Self {
+        Self {
+            image,
+            rotation: 0_f64,
+            intensity: 1_f64,
+        }
+    }
+
+    //the map is assumed to have srgb primaries and gets converted into the `working` space
+    pub fn load(path: impl AsRef<Path>, working: ColorSpace) -> Result<Self, ImageError> {
+        Self::load_in(path, ColorSpace::LinearSrgb, working)
+    }
+
+    //like load, for a map whose colors use the primaries of `space`
+    pub fn load_in(path: impl AsRef<Path>, space: ColorSpace, working: ColorSpace) -> Result<Self, ImageError> {
+        let mut image = read_hdr_image(path)?;
+        space.convert_image(&mut image, working);
+        Ok(Self::new(image))
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn color(&self, dir: &Vec3) -> Color {
+        let d = unit_vector(dir);
+        let phi = d.x().atan2(-d.z()) - deg_to_rad(self.rotation);
+        let theta = d.y().clamp(-1_f64, 1_f64).acos();
+        let u = (phi/(2_f64*PI) + 0.5).rem_euclid(1_f64);
+        let v = theta/PI;
+
+        //wrap around horizontally and clamp at the poles
+        let x = u*self.image.width() as f64;
+        let y = v*self.image.height() as f64;
+        self.intensity*self.image.sample_bilinear(x, y, WrapMode::Repeat, WrapMode::Clamp)
+    }
+}
@@ -0,0 +1,297 @@
+use std::sync::Arc;
+
+use crate::{aabb::Aabb, interval::Interval, ray::{gather_lights, HitRecord, Hittable, HittableList, Ray}, vec3::{Point3, Vec3}};
+
+//number of centroid buckets evaluated per split when building with the surface area heuristic
+const SAH_BUCKETS: usize = 12;
+//leaves never hold more than this many primitives
+const MAX_LEAF_SIZE: usize = 4;
+//cost of one node traversal step, relative to one primitive intersection
+const TRAVERSAL_COST: f64 = 0.125;
+//below this depth splits fall back to the median, which keeps the tree within the traversal stack
+const MAX_SAH_DEPTH: usize = 32;
+const STACK_SIZE: usize = 64;
+
+//a node in the flattened tree. interior nodes store their left child right after themselves
+//and the index of their right child in `offset`, leaves store a range of `prim_indices`
+struct BvhNode {
+    bbox: Aabb,
+    offset: u32,
+    prim_count: u16,
+    axis: u8,
+}
+
+struct PrimInfo {
+    index: usize,
+    bbox: Aabb,
+    centroid: Point3,
+}
+
+//bounding volume hierarchy over a list of hittables, built with a binned surface area heuristic
+//and stored depth-first in one contiguous array
+pub struct Bvh {
+    objects: Vec<Arc<dyn Hittable>>,
+    prim_indices: Vec<u32>,
+    nodes: Vec<BvhNode>,
+}
+
+impl Bvh {
+    pub fn new(list: HittableList) -> Self {
+        Self::from_objects(list.into_objects())
+    }
+
+    pub fn from_objects(objects: Vec<Arc<dyn Hittable>>) -> Self {
+        let mut prims: Vec<PrimInfo> = objects.iter().enumerate().map(|(index, object)| {
+            let bbox = object.bounding_box();
+            PrimInfo { index, bbox, centroid: bbox.centroid() }
+        }).collect();
+
+        let mut bvh = Self {
+            objects,
+            prim_indices: Vec::with_capacity(prims.len()),
+            nodes: Vec::with_capacity(2*prims.len()),
+        };
+        if !prims.is_empty() {
+            bvh.build(&mut prims, 0);
+        }
+        bvh
+    }
+
+    //recursively builds the subtree for `prims`, returning the index of its root node
+    fn build(&mut self, prims: &mut [PrimInfo], depth: usize) -> usize {
+        let bbox = prims.iter().fold(Aabb::EMPTY, |acc, p| Aabb::surrounding(&acc, &p.bbox));
+        let node_idx = self.nodes.len();
+        self.nodes.push(BvhNode { bbox, offset: 0, prim_count: 0, axis: 0 });
+
+        if prims.len() <= 1 {
+            self.make_leaf(node_idx, prims);
+            return node_idx;
+        }
+
+        let centroid_bounds = prims.iter().fold(Aabb::EMPTY, |acc, p| {
+            Aabb::surrounding(&acc, &Aabb::from_points(p.centroid, p.centroid))
+        });
+        let axis = centroid_bounds.longest_axis();
+        let axis_range = centroid_bounds.axis_interval(axis);
+
+        //all centroids coincide, nothing to split on
+        if axis_range.size() <= 0.0001 {
+            if prims.len() <= MAX_LEAF_SIZE {
+                self.make_leaf(node_idx, prims);
+                return node_idx;
+            }
+            let mid = prims.len()/2;
+            return self.split(node_idx, axis, prims, mid, depth);
+        }
+
+        if depth >= MAX_SAH_DEPTH {
+            let mid = prims.len()/2;
+            prims.select_nth_unstable_by(mid, |a, b| a.centroid.get(axis).total_cmp(&b.centroid.get(axis)));
+            return self.split(node_idx, axis, prims, mid, depth);
+        }
+
+        let bucket_of = |p: &PrimInfo| {
+            let b = (SAH_BUCKETS as f64*(p.centroid.get(axis) - axis_range.min)/axis_range.size()) as usize;
+            b.min(SAH_BUCKETS - 1)
+        };
+
+        let mut counts = [0_usize; SAH_BUCKETS];
+        let mut bounds = [Aabb::EMPTY; SAH_BUCKETS];
+        for p in prims.iter() {
+            let b = bucket_of(p);
+            counts[b] += 1;
+            bounds[b] = Aabb::surrounding(&bounds[b], &p.bbox);
+        }
+
+        //sweep from both ends to get the cost of splitting after each bucket
+        let mut costs = [0_f64; SAH_BUCKETS - 1];
+        let mut left_box = Aabb::EMPTY;
+        let mut left_count = 0;
+        for i in 0..SAH_BUCKETS - 1 {
+            left_box = Aabb::surrounding(&left_box, &bounds[i]);
+            left_count += counts[i];
+            costs[i] = left_count as f64*left_box.surface_area();
+        }
+        let mut right_box = Aabb::EMPTY;
+        let mut right_count = 0;
+        for i in (1..SAH_BUCKETS).rev() {
+            right_box = Aabb::surrounding(&right_box, &bounds[i]);
+            right_count += counts[i];
+            costs[i - 1] += right_count as f64*right_box.surface_area();
+        }
+
+        let (best_split, best_cost) = costs.iter().enumerate()
+            .fold((0, f64::INFINITY), |best, (i, &c)| if c < best.1 { (i, c) } else { best });
+        let split_cost = TRAVERSAL_COST + best_cost/bbox.surface_area();
+        let leaf_cost = prims.len() as f64;
+
+        if prims.len() <= MAX_LEAF_SIZE && leaf_cost <= split_cost {
+            self.make_leaf(node_idx, prims);
+            return node_idx;
+        }
+
+        let mut mid = partition(prims, |p| bucket_of(p) <= best_split);
+        if mid == 0 || mid == prims.len() {
+            mid = prims.len()/2;
+            prims.select_nth_unstable_by(mid, |a, b| a.centroid.get(axis).total_cmp(&b.centroid.get(axis)));
+        }
+        self.split(node_idx, axis, prims, mid, depth)
+    }
+
+    fn split(&mut self, node_idx: usize, axis: usize, prims: &mut [PrimInfo], mid: usize, depth: usize) -> usize {
+        let (left, right) = prims.split_at_mut(mid);
+        self.build(left, depth + 1);
+        let right_idx = self.build(right, depth + 1);
+        let node = &mut self.nodes[node_idx];
+        node.offset = right_idx as u32;
+        node.axis = axis as u8;
+        node_idx
+    }
+
+    fn make_leaf(&mut self, node_idx: usize, prims: &[PrimInfo]) {
+        let node = &mut self.nodes[node_idx];
+        node.offset = self.prim_indices.len() as u32;
+        node.prim_count = prims.len() as u16;
+        self.prim_indices.extend(prims.iter().map(|p| p.index as u32));
+    }
+}
+
+//reorders `prims` so every element matching `pred` comes first, returning how many matched
+fn partition(prims: &mut [PrimInfo], pred: impl Fn(&PrimInfo) -> bool) -> usize {
+    let mut mid = 0;
+    for i in 0..prims.len() {
+        if pred(&prims[i]) {
+            prims.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+impl Hittable for Bvh {
+    fn hit(&self, ray :&Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let origin = ray.origin();
+        let dir = ray.dir();
+        let inv_dir = Vec3::new(1_f64/dir.x(), 1_f64/dir.y(), 1_f64/dir.z());
+        let dir_is_neg = [dir.x() < 0_f64, dir.y() < 0_f64, dir.z() < 0_f64];
+
+        let mut hit_anything = false;
+        let mut closest_so_far = ray_t.max;
+        let mut stack = [0_usize; STACK_SIZE];
+        let mut stack_len = 0;
+        let mut node_idx = 0;
+
+        loop {
+            let node = &self.nodes[node_idx];
+            if node.bbox.hit_with_inv_dir(&origin, &inv_dir, Interval::new(ray_t.min, closest_so_far)) {
+                if node.prim_count > 0 {
+                    let start = node.offset as usize;
+                    for &prim in &self.prim_indices[start..start + node.prim_count as usize] {
+                        if self.objects[prim as usize].hit(ray, Interval::new(ray_t.min, closest_so_far), rec) {
+                            hit_anything = true;
+                            closest_so_far = rec.t;
+                            rec.object_id = prim;
+                        }
+                    }
+                } else {
+                    //visit the near child first so the far one is more likely to be culled
+                    let (near, far) = match dir_is_neg[node.axis as usize] {
+                        true => (node.offset as usize, node_idx + 1),
+                        false => (node_idx + 1, node.offset as usize),
+                    };
+                    stack[stack_len] = far;
+                    stack_len += 1;
+                    node_idx = near;
+                    continue;
+                }
+            }
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            node_idx = stack[stack_len];
+        }
+        hit_anything
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::EMPTY, |root| root.bbox)
+    }
+
+    fn lights(&self) -> Vec<Arc<dyn Hittable>> {
+        gather_lights(&self.objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, helper::{random_f64_range, Rng, INFINITY}, material::Lambertian, sphere::Sphere, triangle::Triangle, vec3::unit_vector};
+
+    fn random_point(rng: &mut Rng, extent: f64) -> Point3 {
+        Point3::random_range(rng, -extent, extent)
+    }
+
+    //shoots rays from all around the scene and expects the same closest hit from both
+    fn assert_same_hits(list: &HittableList, bvh: &Bvh, rng: &mut Rng) {
+        for _ in 0..2000 {
+            let origin = random_point(rng, 12_f64);
+            let target = random_point(rng, 6_f64);
+            let ray = Ray::new(origin, unit_vector(&(target - origin)));
+            let (mut expected, mut actual) = (HitRecord::new_empty(), HitRecord::new_empty());
+            let hit = list.hit(&ray, Interval::new(0.001, INFINITY), &mut expected);
+            assert_eq!(bvh.hit(&ray, Interval::new(0.001, INFINITY), &mut actual), hit);
+            if hit {
+                assert_eq!(actual.object_id, expected.object_id);
+                assert!((actual.t - expected.t).abs() < 1e-9, "t {} != {}", actual.t, expected.t);
+            }
+        }
+    }
+
+    fn random_scene(rng: &mut Rng, count: usize) -> HittableList {
+        let mat = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let mut list = HittableList::new();
+        for i in 0..count {
+            let center = random_point(rng, 5_f64);
+            match i % 3 {
+                0 => list.add(Arc::new(Triangle::new(center, center + random_point(rng, 1_f64), center + random_point(rng, 1_f64), mat.clone()))),
+                _ => list.add(Arc::new(Sphere::new(center, random_f64_range(rng, 0.05, 0.8), mat.clone()))),
+            }
+        }
+        list
+    }
+
+    #[test]
+    fn matches_hittable_list() {
+        let mut rng = Rng::new(7);
+        for count in [1, 2, 5, 40, 300] {
+            let list = random_scene(&mut rng, count);
+            let bvh = Bvh::from_objects(list.objects().to_vec());
+            assert_same_hits(&list, &bvh, &mut rng);
+        }
+    }
+
+    #[test]
+    fn matches_hittable_list_with_shared_centroids() {
+        //every primitive in one spot, so the sah finds nothing to split and has to fall back
+        let mat = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let mut rng = Rng::new(11);
+        let mut list = HittableList::new();
+        for _ in 0..50 {
+            list.add(Arc::new(Sphere::new(Point3::new_empty(), random_f64_range(&mut rng, 0.1, 3_f64), mat.clone())));
+        }
+        let bvh = Bvh::from_objects(list.objects().to_vec());
+        assert_same_hits(&list, &bvh, &mut rng);
+    }
+
+    #[test]
+    fn empty_bvh_hits_nothing() {
+        let bvh = Bvh::new(HittableList::new());
+        let ray = Ray::new(Point3::new_empty(), Vec3::new(0_f64, 0_f64, -1_f64));
+        assert!(!bvh.hit(&ray, Interval::new(0.001, INFINITY), &mut HitRecord::new_empty()));
+    }
+}
@@ -1,6 +1,37 @@
-use std::io::{self, Write};
-use crate::{color::{write_color, Color}, helper::deg_to_rad, random_f64, interval::Interval, ray::{HitRecord, Hittable, Ray}, vec3::{cross, random_in_unit_disk, unit_vector, Point3, Vec3}};
-use crate::helper::INFINITY;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::{accumulator::Accumulator, aov::{Aov, AovPixel}, background::{Background, GradientBackground}, color::Color, colorspace::ColorSpace, helper::{deg_to_rad, INFINITY}, image::Image, integrator::{Integrator, PathIntegrator, Scene}, interval::Interval, light::{LightList, PunctualLight}, progress::{CancelToken, ProgressObserver, RenderProgress, SilentProgress, TileRect}, ray::{HitRecord, Hittable, Ray}, sampler::{Sampler, SamplerKind, LENS_DIMENSION, PIXEL_DIMENSION}, vec3::{cross, dot, random_in_unit_disk, unit_vector, Point3, Vec3}};
+
+//side length in pixels of the square tiles handed out to render threads
+const TILE_SIZE: i32 = 16;
+
+struct Tile {
+    x0: i32,
+    y0: i32,
+    width: i32,
+    height: i32,
+}
+
+//per-pixel adaptive sampling: every pixel gets `min_samples`, then more batches of
+//`min_samples` until the standard error of its mean luminance, relative to that mean, drops
+//below `noise_threshold` or `max_samples` is reached
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveSampling {
+    pub min_samples: i32,
+    pub max_samples: i32,
+    pub noise_threshold: f64,
+}
+
+//progressive rendering: passes of `samples_per_pass` samples are added to an accumulator until
+//every pixel has `target_samples` or the pass that crosses `time_budget` finishes
+#[derive(Clone, Copy, Debug)]
+pub struct ProgressiveSettings {
+    pub samples_per_pass: u32,
+    pub target_samples: Option<u32>,
+    pub time_budget: Option<Duration>,
+}
 
 pub struct Camera {
     aspect_ratio: f64,
@@ -19,11 +50,23 @@ pub struct Camera {
     pub vup: Vec3,      // camera relative up direction
     pub defocus_angle: f64,
     pub focus_dist: f64,
+    pub background: Arc<dyn Background>,
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    pub adaptive: Option<AdaptiveSampling>, //when set, replaces the fixed samples_per_pixel
+    pub progress: Arc<dyn ProgressObserver>,
+    pub cancel: CancelToken, //once cancelled, renders stop after their current tiles
+    pub punctual_lights: Vec<Arc<dyn PunctualLight>>, //point, spot and directional lights, on top of emissive shapes
+    pub integrator: Arc<dyn Integrator>,
+    pub transparent_background: bool, //for alpha output, see Scene::transparent_background
+    pub working_space: ColorSpace, //primaries of the scene's colors, for luminance estimates
     u : Vec3,
     v : Vec3, 
     w : Vec3, 
     defocus_disk_u : Vec3,
     defocus_disk_v : Vec3,
+    lights : LightList, //gathered from the world at the start of every render
+    luminance_weights : Vec3, //of the working space, cached by init
 }
 
 impl Camera {
@@ -48,42 +91,268 @@ impl Camera {
             vfov,
             defocus_angle : 0_f64,
             focus_dist : 0_f64,
+            background : Arc::new(GradientBackground::sky()),
+            seed : 0,
+            sampler : SamplerKind::Independent,
+            adaptive : None,
+            progress : Arc::new(SilentProgress),
+            cancel : CancelToken::new(),
+            punctual_lights : Vec::new(),
+            integrator : Arc::new(PathIntegrator::new()),
+            transparent_background : false,
+            working_space : ColorSpace::LinearSrgb,
             defocus_disk_u : Vec3::new_empty(),
             defocus_disk_v : Vec3::new_empty(),
+            lights : LightList::new(Vec::new(), Vec::new()),
+            luminance_weights : Vec3::new_empty(),
         }
     }
 
-    pub fn render(&mut self, world : &dyn Hittable) {
+    //tiles not reached before a cancel are left black in the returned image
+    pub fn render(&mut self, world : &dyn Hittable) -> Image {
+        self.init();
+        self.lights = LightList::from_world(world, &self.punctual_lights);
+
+        let start = Instant::now();
+        let framebuffer = Mutex::new(Image::new(self.img_width as usize, self.img_height as usize));
+        let progress = self.for_each_tile(0, Some(1), start, |tile| {
+            let pixels = self.render_tile(tile, world);
+            let mut framebuffer = framebuffer.lock().unwrap();
+            for (i, color) in pixels.into_iter().enumerate() {
+                let x = tile.x0 + i as i32 % tile.width;
+                let y = tile.y0 + i as i32 / tile.width;
+                framebuffer.set(x as usize, y as usize, color);
+            }
+        });
+
+        self.progress.render_finished(&progress, self.cancel.is_cancelled());
+        framebuffer.into_inner().unwrap()
+    }
+
+    //adds passes of samples to `acc`, continuing each pixel's sample sequence where it left off
+    //so a resumed render matches one that was never interrupted. `on_pass` runs after every
+    //pass, e.g. to checkpoint the accumulator to disk. adaptive sampling is not used here.
+    //a cancelled pass still leaves `acc` consistent, its unfinished tiles just have fewer samples
+    pub fn render_progressive(&mut self, world: &dyn Hittable, acc: &mut Accumulator, settings: &ProgressiveSettings, mut on_pass: impl FnMut(&Accumulator)) {
         self.init();
-        
-        //write header to stdout
-        println!("P3");
-        println!("{} {}", self.img_width, self.img_height);
-        println!("255");
-        for y in 0..self.img_height {
-
-            eprint!("\rScanlines remaining: {}", self.img_height - y);
-            io::stderr().flush().unwrap(); // Ensure the progress is displayed immediately
-
-            for x in 0..self.img_width {
-                let mut pixel_color : Color = Color::new_empty();
-                for _ in 0..self.samples_per_pixel {
-                    let ray = self.get_ray(x, y);
-                    pixel_color += self.ray_color(&ray, world, self.max_depth);
+        self.lights = LightList::from_world(world, &self.punctual_lights);
+        assert!(acc.width() == self.img_width as usize && acc.height() == self.img_height as usize,
+            "accumulator is {}x{} but the camera renders {}x{}", acc.width(), acc.height(), self.img_width, self.img_height);
+
+        let start = Instant::now();
+        let samples_per_pass = settings.samples_per_pass.max(1);
+        let passes_total = settings.target_samples.map(|target| target.saturating_sub(acc.min_samples()).div_ceil(samples_per_pass));
+
+        let mut pass = 0;
+        let mut progress = RenderProgress { tiles_done: 0, tiles_total: 0, pass, passes_total, elapsed: start.elapsed() };
+        loop {
+            if settings.target_samples.is_some_and(|target| acc.min_samples() >= target) {
+                break;
+            }
+            if settings.time_budget.is_some_and(|budget| start.elapsed() >= budget) || self.cancel.is_cancelled() {
+                break;
+            }
+
+            let shared = Mutex::new(&mut *acc);
+            progress = self.for_each_tile(pass, passes_total, start, |tile| {
+                let mut sampler = self.sampler.create(self.seed, samples_per_pass);
+                let mut results = Vec::with_capacity((tile.width*tile.height) as usize);
+                {
+                    let acc = shared.lock().unwrap();
+                    for y in tile.y0..tile.y0 + tile.height {
+                        for x in tile.x0..tile.x0 + tile.width {
+                            let have = acc.samples(x as usize, y as usize);
+                            let want = settings.target_samples.map_or(samples_per_pass, |t| t.saturating_sub(have).min(samples_per_pass));
+                            results.push((x, y, have, want));
+                        }
+                    }
+                }
+
+                let results: Vec<(i32, i32, Color, u32)> = results.into_iter().map(|(x, y, have, want)| {
+                    let mut sum = Color::new_empty();
+                    for index in have..have + want {
+                        sum += self.sample_pixel(x, y, index, world, sampler.as_mut());
+                    }
+                    (x, y, sum, want)
+                }).collect();
+
+                let mut acc = shared.lock().unwrap();
+                for (x, y, sum, count) in results {
+                    acc.add(x as usize, y as usize, sum, count);
                 }
-                let resultant_color = self.pixels_sample_scale*pixel_color;
-                write_color(&resultant_color);
+            });
+            on_pass(acc);
+            pass += 1;
+        }
+        self.progress.render_finished(&progress, self.cancel.is_cancelled());
+    }
+
+    //hands tiles out to one worker per core, each pulling the next unrendered tile until none are
+    //left or the render is cancelled. returns the progress after the last finished tile
+    fn for_each_tile(&self, pass: u32, passes_total: Option<u32>, start: Instant, work: impl Fn(&Tile) + Sync) -> RenderProgress {
+        let tiles = self.tiles();
+        let next_tile = AtomicUsize::new(0);
+        let tiles_done = AtomicUsize::new(0);
+        let num_threads = thread::available_parallelism().map_or(1, |n| n.get());
+
+        thread::scope(|s| {
+            for _ in 0..num_threads {
+                s.spawn(|| {
+                    while !self.cancel.is_cancelled() {
+                        let idx = next_tile.fetch_add(1, Ordering::Relaxed);
+                        let Some(tile) = tiles.get(idx) else { break };
+                        work(tile);
+
+                        let progress = RenderProgress {
+                            tiles_done: tiles_done.fetch_add(1, Ordering::Relaxed) + 1,
+                            tiles_total: tiles.len(),
+                            pass,
+                            passes_total,
+                            elapsed: start.elapsed(),
+                        };
+                        let rect = TileRect { x0: tile.x0 as usize, y0: tile.y0 as usize, width: tile.width as usize, height: tile.height as usize };
+                        self.progress.tile_finished(&rect, &progress);
+                    }
+                });
+            }
+        });
+
+        RenderProgress { tiles_done: tiles_done.into_inner(), tiles_total: tiles.len(), pass, passes_total, elapsed: start.elapsed() }
+    }
+
+    //first hit aovs, one image per entry of `aovs`. traced separately from the beauty image but
+    //with the same camera rays as its first samples_per_pixel samples, so they line up with it
+    pub fn render_aovs(&mut self, world: &dyn Hittable, aovs: &[Aov]) -> Vec<Image> {
+        self.init();
+
+        let start = Instant::now();
+        let samples = self.samples_per_pixel.max(1) as u32;
+        let layers = Mutex::new(vec![Image::new(self.img_width as usize, self.img_height as usize); aovs.len()]);
+        let progress = self.for_each_tile(0, Some(1), start, |tile| {
+            let mut sampler = self.sampler.create(self.seed, samples);
+            let mut pixels = Vec::with_capacity((tile.width*tile.height) as usize);
+            for y in tile.y0..tile.y0 + tile.height {
+                for x in tile.x0..tile.x0 + tile.width {
+                    let mut values = vec![AovPixel::new(); aovs.len()];
+                    for index in 0..samples {
+                        sampler.start_pixel_sample(x, y, index);
+                        let ray = self.get_ray(x, y, sampler.as_mut());
+                        let mut hit_rec = HitRecord::new_empty();
+                        let hit = world.hit(&ray, Interval::new(0.001, INFINITY), &mut hit_rec);
+                        for (value, aov) in values.iter_mut().zip(aovs) {
+                            value.add(*aov, &ray, hit.then_some(&hit_rec));
+                        }
+                    }
+                    pixels.push((x, y, values));
+                }
+            }
+
+            let mut layers = layers.lock().unwrap();
+            for (x, y, values) in pixels {
+                for ((layer, value), aov) in layers.iter_mut().zip(values).zip(aovs) {
+                    layer.set(x as usize, y as usize, value.resolve(*aov));
+                }
+            }
+        });
+
+        self.progress.render_finished(&progress, self.cancel.is_cancelled());
+        layers.into_inner().unwrap()
+    }
+
+    fn tiles(&self) -> Vec<Tile> {
+        let mut tiles = Vec::new();
+        for y0 in (0..self.img_height).step_by(TILE_SIZE as usize) {
+            for x0 in (0..self.img_width).step_by(TILE_SIZE as usize) {
+                tiles.push(Tile {
+                    x0,
+                    y0,
+                    width: TILE_SIZE.min(self.img_width - x0),
+                    height: TILE_SIZE.min(self.img_height - y0),
+                });
+            }
+        }
+        tiles
+    }
+
+    fn render_tile(&self, tile: &Tile, world: &dyn Hittable) -> Vec<Color> {
+        let mut pixels = Vec::with_capacity((tile.width*tile.height) as usize);
+        //adaptive batches are sized so each one is a complete set for the stratified samplers
+        let batch_size = self.adaptive.map_or(self.samples_per_pixel, |a| a.min_samples.max(1));
+        let mut sampler = self.sampler.create(self.seed, batch_size as u32);
+        for y in tile.y0..tile.y0 + tile.height {
+            for x in tile.x0..tile.x0 + tile.width {
+                let pixel_color = match self.adaptive {
+                    Some(adaptive) => self.render_pixel_adaptive(x, y, world, sampler.as_mut(), &adaptive),
+                    None => {
+                        let mut pixel_color : Color = Color::new_empty();
+                        for s in 0..self.samples_per_pixel {
+                            pixel_color += self.sample_pixel(x, y, s as u32, world, sampler.as_mut());
+                        }
+                        self.pixels_sample_scale*pixel_color
+                    }
+                };
+                pixels.push(pixel_color);
+            }
+        }
+        pixels
+    }
+
+    fn render_pixel_adaptive(&self, x: i32, y: i32, world: &dyn Hittable, sampler: &mut dyn Sampler, adaptive: &AdaptiveSampling) -> Color {
+        let batch_size = adaptive.min_samples.max(1);
+        let max_samples = adaptive.max_samples.max(batch_size);
+
+        //welford's running mean and variance of the sample luminance
+        let mut sum = Color::new_empty();
+        let mut count = 0;
+        let mut mean = 0_f64;
+        let mut m2 = 0_f64;
+        while count < max_samples {
+            for _ in 0..batch_size.min(max_samples - count) {
+                let sample = self.sample_pixel(x, y, count as u32, world, sampler);
+                sum += sample;
+                count += 1;
+                let lum = dot(&self.luminance_weights, &sample);
+                let delta = lum - mean;
+                mean += delta/count as f64;
+                m2 += delta*(lum - mean);
+            }
+            if count < 2 {
+                continue;
+            }
+            let std_error = (m2/(count - 1) as f64/count as f64).sqrt();
+            //floor the mean so near black pixels don't chase noise no one can see
+            if std_error <= adaptive.noise_threshold*mean.max(1e-3) {
+                break;
             }
         }
-        eprintln!("\rDone");
+        sum/count as f64
+    }
+
+    fn sample_pixel(&self, x: i32, y: i32, index: u32, world: &dyn Hittable, sampler: &mut dyn Sampler) -> Color {
+        sampler.start_pixel_sample(x, y, index);
+        let ray = self.get_ray(x, y, sampler);
+        let scene = Scene {
+            world,
+            lights: &self.lights,
+            background: self.background.as_ref(),
+            max_depth: self.max_depth,
+            transparent_background: self.transparent_background,
+        };
+        self.integrator.li(ray, &scene, sampler)
+    }
+
+    //width and height of the image render will produce, e.g. to size an accumulator up front
+    pub fn image_size(&self) -> (usize, usize) {
+        let img_height = (self.img_width as f64 / self.aspect_ratio) as i32;
+        (self.img_width as usize, img_height.max(1) as usize)
     }
 
     fn init(&mut self) {
-        self.img_height = (self.img_width as f64 / self.aspect_ratio) as i32;
-        self.img_height = if self.img_height < 1 { 1 } else { self.img_height };
+        self.img_height = self.image_size().1 as i32;
 
         self.pixels_sample_scale = 1.0/self.samples_per_pixel as f64;
         self.center = self.lookfrom;
+        self.luminance_weights = self.working_space.luminance_weights();
 
         //determine viewport dimensions
         let theta = deg_to_rad(self.vfov);
@@ -114,48 +383,31 @@ impl Camera {
         self.defocus_disk_v = defocus_radius * self.u;
     }
 
-    #[allow(clippy::only_used_in_recursion)]
-    fn ray_color(&self, ray: &Ray, world: &dyn Hittable, depth: i32) -> Color {
-        if depth <= 0 {
-            return Color::new_empty();
-        }
-
-        let mut hit_rec : HitRecord = HitRecord::new_empty();
-        if world.hit(ray, Interval::new(0.001, INFINITY), &mut hit_rec) {
-            let mut scattered_ray : Ray = Ray::new_empty();
-            let mut attenuation : Color =  Color::new_empty();
-            if hit_rec.mat.clone().unwrap().scatter(ray, &hit_rec, &mut attenuation, &mut scattered_ray) {
-                return attenuation * self.ray_color(&scattered_ray, world, depth-1);
-            }
-            return Color::new(0_f64, 0_f64, 0_f64);
-        }
-        let unit_dir : Vec3 = unit_vector(&ray.dir());
-        let a = 0.5_f64*(unit_dir.y() + 1_f64);
-        (1_f64-a)*Color::new(1_f64, 1_f64, 1_f64) + a*Color::new(0.5_f64, 0.7_f64, 1_f64)
-    }
-    
-    fn get_ray(&self, x : i32, y: i32) -> Ray {
+    fn get_ray(&self, x : i32, y: i32, sampler: &mut dyn Sampler) -> Ray {
         // Construct a camera ray originating from the origin and directed at randomly sampled
         // point around the pixel location i, j.
-        let offset = self.sample_square();
+        let offset = self.sample_square(sampler);
         let pixel_sample = self.pixel00_loc
             + ((x as f64 + offset.x()) * self.pixel_delta_u)
             + ((y as f64 + offset.y()) * self.pixel_delta_v);
         let ray_origin = match self.defocus_angle {
             x if x <= 0_f64 => self.center,
-            _ => self.defocus_disk_sample(),
+            _ => self.defocus_disk_sample(sampler),
         };
         let ray_dir = pixel_sample - ray_origin;
         Ray::new(ray_origin, ray_dir)
     }
 
-    fn sample_square(&self) -> Vec3 {
+    fn sample_square(&self, sampler: &mut dyn Sampler) -> Vec3 {
         // Returns the vector to a random point in the [-.5,-.5]-[+.5,+.5] unit square.
-        Vec3::new(random_f64()-0.5, random_f64()-0.5, 0_f64)
+        sampler.set_dimension(PIXEL_DIMENSION);
+        let u = sampler.get_2d();
+        Vec3::new(u[0]-0.5, u[1]-0.5, 0_f64)
     }
 
-    fn defocus_disk_sample(&self) -> Point3 {
-        let p = random_in_unit_disk();
+    fn defocus_disk_sample(&self, sampler: &mut dyn Sampler) -> Point3 {
+        sampler.set_dimension(LENS_DIMENSION);
+        let p = random_in_unit_disk(sampler.get_2d());
         self.center + (p.x() * self.defocus_disk_u) + (p.y() * self.defocus_disk_v)
     }
 }
@@ -1,19 +1,44 @@
-use crate::{interval::Interval, Vec3};
+use std::io::{self, Write};
+
+use crate::{interval::Interval, vec3::Vec3};
 
 pub type Color = Vec3;
 
-pub fn write_color(pixel_color: &Color) {
+pub fn write_color(out: &mut impl Write, pixel_color: &Color) -> io::Result<()> {
     let intensity: Interval = Interval::new(0.0, 0.999);
 
-    //apply the linear-to-gamma transformation directly with conditional expressions
-    let r = if pixel_color.x() > 0.0 { pixel_color.x().sqrt() } else { 0.0 };
-    let g = if pixel_color.y() > 0.0 { pixel_color.y().sqrt() } else { 0.0 };
-    let b = if pixel_color.z() > 0.0 { pixel_color.z().sqrt() } else { 0.0 };
+    //encode with the srgb transfer function, anything out of range is clipped
+    let r = linear_to_srgb(pixel_color.x().max(0.0));
+    let g = linear_to_srgb(pixel_color.y().max(0.0));
+    let b = linear_to_srgb(pixel_color.z().max(0.0));
 
     //clamp the values to the interval and convert to u32
     let ir: u32 = (256.0 * intensity.clamp(r)) as u32;
     let ig: u32 = (256.0 * intensity.clamp(g)) as u32;
     let ib: u32 = (256.0 * intensity.clamp(b)) as u32;
 
-    println!("{} {} {}", ir, ig, ib);
+    writeln!(out, "{} {} {}", ir, ig, ib)
+}
+
+//the piecewise srgb transfer function, for encoding linear values in [0, 1] into image files
+pub fn linear_to_srgb(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        12.92*linear
+    } else {
+        1.055*linear.powf(1.0/2.4) - 0.055
+    }
+}
+
+//inverse of the piecewise srgb transfer function, for decoding 8-bit image files
+pub fn srgb_to_linear(encoded: f64) -> f64 {
+    if encoded <= 0.04045 {
+        encoded/12.92
+    } else {
+        ((encoded + 0.055)/1.055).powf(2.4)
+    }
+}
+
+//relative luminance of a linear rec.709 color
+pub fn luminance(color: &Color) -> f64 {
+    0.2126*color.x() + 0.7152*color.y() + 0.0722*color.z()
 }
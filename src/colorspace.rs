@@ -0,0 +1,264 @@
+use std::fmt;
+use std::ops::Mul;
+use std::str::FromStr;
+
+use crate::{color::Color, image::Image};
+
+//3x3 matrix acting on colors, stored row by row
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat3(pub [[f64; 3]; 3]);
+
+impl Mat3 {
+    pub const IDENTITY: Mat3 = Mat3([[1_f64, 0_f64, 0_f64], [0_f64, 1_f64, 0_f64], [0_f64, 0_f64, 1_f64]]);
+
+    pub fn diagonal(d: Color) -> Self {
+        Mat3([[d.x(), 0_f64, 0_f64], [0_f64, d.y(), 0_f64], [0_f64, 0_f64, d.z()]])
+    }
+
+    pub fn from_columns(a: Color, b: Color, c: Color) -> Self {
+        Mat3([[a.x(), b.x(), c.x()], [a.y(), b.y(), c.y()], [a.z(), b.z(), c.z()]])
+    }
+
+    pub fn transform(&self, c: &Color) -> Color {
+        let m = &self.0;
+        Color::new(
+            m[0][0]*c.x() + m[0][1]*c.y() + m[0][2]*c.z(),
+            m[1][0]*c.x() + m[1][1]*c.y() + m[1][2]*c.z(),
+            m[2][0]*c.x() + m[2][1]*c.y() + m[2][2]*c.z(),
+        )
+    }
+
+    //through the adjugate, the matrices here are all well conditioned
+    pub fn inverse(&self) -> Self {
+        let m = &self.0;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0]*m[r1][c1] - m[r0][c1]*m[r1][c0];
+        let adjugate = [
+            [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+            [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+            [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+        ];
+        let det = m[0][0]*adjugate[0][0] + m[0][1]*adjugate[1][0] + m[0][2]*adjugate[2][0];
+        Mat3(adjugate.map(|row| row.map(|v| v/det)))
+    }
+}
+
+impl Mul for Mat3 {
+    type Output = Mat3;
+
+    fn mul(self, rhs: Mat3) -> Mat3 {
+        let mut out = [[0_f64; 3]; 3];
+        for (i, row) in out.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = (0..3).map(|k| self.0[i][k]*rhs.0[k][j]).sum();
+            }
+        }
+        Mat3(out)
+    }
+}
+
+//cie xy coordinates of the red, green and blue primaries and the white point of an rgb space
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Chromaticities {
+    pub red: [f64; 2],
+    pub green: [f64; 2],
+    pub blue: [f64; 2],
+    pub white: [f64; 2],
+}
+
+const D65: [f64; 2] = [0.3127, 0.3290];
+//the white of the aces spaces, close to d60
+const ACES_WHITE: [f64; 2] = [0.32168, 0.33767];
+
+//bradford cone response matrix for adapting between white points
+const BRADFORD: Mat3 = Mat3([
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+]);
+
+//linear rgb spaces colors can be authored, rendered and written in. all of them hold scene
+//linear values, transfer functions are up to the file formats
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorSpace {
+    #[default]
+    LinearSrgb, //srgb / rec.709 primaries, what the renderer has always assumed
+    AcesCg, //aces ap1, the wide gamut working space of most film pipelines
+    DisplayP3, //dci-p3 primaries with a d65 white, used by apple displays
+    Rec2020, //uhdtv primaries, wider still
+}
+
+impl ColorSpace {
+    pub fn chromaticities(&self) -> Chromaticities {
+        match self {
+            ColorSpace::LinearSrgb => Chromaticities { red: [0.64, 0.33], green: [0.30, 0.60], blue: [0.15, 0.06], white: D65 },
+            ColorSpace::AcesCg => Chromaticities { red: [0.713, 0.293], green: [0.165, 0.830], blue: [0.128, 0.044], white: ACES_WHITE },
+            ColorSpace::DisplayP3 => Chromaticities { red: [0.680, 0.320], green: [0.265, 0.690], blue: [0.150, 0.060], white: D65 },
+            ColorSpace::Rec2020 => Chromaticities { red: [0.708, 0.292], green: [0.170, 0.797], blue: [0.131, 0.046], white: D65 },
+        }
+    }
+
+    //rgb to cie xyz, scaled so that rgb white has a luminance of 1
+    pub fn to_xyz(&self) -> Mat3 {
+        let c = self.chromaticities();
+        let primaries = Mat3::from_columns(xy_to_xyz(c.red), xy_to_xyz(c.green), xy_to_xyz(c.blue));
+        let scale = primaries.inverse().transform(&xy_to_xyz(c.white));
+        primaries*Mat3::diagonal(scale)
+    }
+
+    //weights giving the relative luminance of a color in this space, the y row of to_xyz
+    pub fn luminance_weights(&self) -> Color {
+        let y = self.to_xyz().0[1];
+        Color::new(y[0], y[1], y[2])
+    }
+
+    //matrix taking colors in this space to `target`, with bradford adaptation between white points
+    pub fn conversion(&self, target: ColorSpace) -> Mat3 {
+        if *self == target {
+            return Mat3::IDENTITY;
+        }
+        let source_white = self.chromaticities().white;
+        let target_white = target.chromaticities().white;
+        let adaptation = match source_white == target_white {
+            true => Mat3::IDENTITY,
+            false => {
+                let source_cone = BRADFORD.transform(&xy_to_xyz(source_white));
+                let target_cone = BRADFORD.transform(&xy_to_xyz(target_white));
+                let gain = Color::new(target_cone.x()/source_cone.x(), target_cone.y()/source_cone.y(), target_cone.z()/source_cone.z());
+                BRADFORD.inverse()*Mat3::diagonal(gain)*BRADFORD
+            }
+        };
+        target.to_xyz().inverse()*adaptation*self.to_xyz()
+    }
+
+    pub fn convert(&self, color: &Color, target: ColorSpace) -> Color {
+        self.conversion(target).transform(color)
+    }
+
+    //converts every pixel in place
+    pub fn convert_image(&self, image: &mut Image, target: ColorSpace) {
+        if *self == target {
+            return;
+        }
+        let m = self.conversion(target);
+        for pixel in image.pixels_mut() {
+            *pixel = m.transform(pixel);
+        }
+    }
+}
+
+impl FromStr for ColorSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "srgb" | "linear-srgb" | "rec709" => Ok(ColorSpace::LinearSrgb),
+            "acescg" => Ok(ColorSpace::AcesCg),
+            "p3" | "display-p3" => Ok(ColorSpace::DisplayP3),
+            "rec2020" => Ok(ColorSpace::Rec2020),
+            _ => Err(format!("unknown color space '{}', expected srgb, acescg, display-p3 or rec2020", s)),
+        }
+    }
+}
+
+impl fmt::Display for ColorSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColorSpace::LinearSrgb => "srgb",
+            ColorSpace::AcesCg => "acescg",
+            ColorSpace::DisplayP3 => "display-p3",
+            ColorSpace::Rec2020 => "rec2020",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+//xyz of a chromaticity at a luminance of 1
+fn xy_to_xyz(xy: [f64; 2]) -> Color {
+    Color::new(xy[0]/xy[1], 1_f64, (1_f64 - xy[0] - xy[1])/xy[1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [ColorSpace; 4] = [ColorSpace::LinearSrgb, ColorSpace::AcesCg, ColorSpace::DisplayP3, ColorSpace::Rec2020];
+
+    fn assert_matrix_close(actual: &Mat3, expected: &Mat3, tolerance: f64) {
+        for (a, e) in actual.0.iter().flatten().zip(expected.0.iter().flatten()) {
+            assert!((a - e).abs() <= tolerance, "{:?} != {:?}", actual.0, expected.0);
+        }
+    }
+
+    #[test]
+    fn srgb_to_acescg_matches_the_published_matrix() {
+        //bradford adapted from d65 to the aces white
+        let expected = Mat3([
+            [0.6130974024, 0.3395231462, 0.0473794514],
+            [0.0701937225, 0.9163538791, 0.0134523985],
+            [0.0206155929, 0.1095697729, 0.8698146342],
+        ]);
+        assert_matrix_close(&ColorSpace::LinearSrgb.conversion(ColorSpace::AcesCg), &expected, 1e-4);
+    }
+
+    #[test]
+    fn srgb_to_rec2020_matches_the_published_matrix() {
+        //itu-r bt.2087, both spaces share d65 so there's no adaptation
+        let expected = Mat3([
+            [0.6274, 0.3293, 0.0433],
+            [0.0691, 0.9195, 0.0114],
+            [0.0164, 0.0880, 0.8956],
+        ]);
+        assert_matrix_close(&ColorSpace::LinearSrgb.conversion(ColorSpace::Rec2020), &expected, 1e-4);
+    }
+
+    #[test]
+    fn srgb_to_xyz_matches_the_published_matrix() {
+        //as listed in css color 4, which also derives it from the d65 chromaticity
+        let expected = Mat3([
+            [0.4123908, 0.3575843, 0.1804808],
+            [0.2126390, 0.7151687, 0.0721923],
+            [0.0193308, 0.1191948, 0.9505322],
+        ]);
+        assert_matrix_close(&ColorSpace::LinearSrgb.to_xyz(), &expected, 1e-6);
+        let weights = ColorSpace::LinearSrgb.luminance_weights();
+        assert!((weights.x() - 0.2126390).abs() < 1e-6 && (weights.y() - 0.7151687).abs() < 1e-6 && (weights.z() - 0.0721923).abs() < 1e-6);
+    }
+
+    #[test]
+    fn conversions_there_and_back_are_the_identity() {
+        for a in ALL {
+            for b in ALL {
+                assert_matrix_close(&(a.conversion(b)*b.conversion(a)), &Mat3::IDENTITY, 1e-12);
+            }
+            assert_matrix_close(&a.conversion(a), &Mat3::IDENTITY, 1e-12);
+        }
+    }
+
+    #[test]
+    fn white_stays_white() {
+        //every space is tagged with its own white point, which adaptation maps onto each other
+        let white = Color::new(1_f64, 1_f64, 1_f64);
+        for a in ALL {
+            for b in ALL {
+                let c = a.convert(&white, b);
+                assert!((c.x() - 1_f64).abs() < 1e-9 && (c.y() - 1_f64).abs() < 1e-9 && (c.z() - 1_f64).abs() < 1e-9, "{} to {}: {:?}", a, b, c);
+            }
+        }
+    }
+
+    #[test]
+    fn luminance_weights_sum_to_one() {
+        for space in ALL {
+            let w = space.luminance_weights();
+            assert!((w.x() + w.y() + w.z() - 1_f64).abs() < 1e-9, "{}", space);
+        }
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for space in ALL {
+            assert_eq!(space.to_string().parse::<ColorSpace>(), Ok(space));
+        }
+        assert!("xyz".parse::<ColorSpace>().is_err());
+    }
+}
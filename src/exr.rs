@@ -0,0 +1,460 @@
+use std::io::{self, Write};
+
+use crate::{colorspace::ColorSpace, image::Image};
+
+//scanline openexr writer, enough of the format for renders and their aovs: any number of
+//channels, uint/half/float pixels, and either no compression or zip
+
+const MAGIC: u32 = 20000630;
+//version 2, single part scanline file
+const VERSION: u32 = 2;
+//set when an attribute or channel name is longer than 31 bytes
+const LONG_NAMES_FLAG: u32 = 0x400;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExrPixelType {
+    Uint,
+    Half,
+    Float,
+}
+
+impl ExrPixelType {
+    fn code(&self) -> i32 {
+        match self {
+            ExrPixelType::Uint => 0,
+            ExrPixelType::Half => 1,
+            ExrPixelType::Float => 2,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            ExrPixelType::Half => 2,
+            ExrPixelType::Uint | ExrPixelType::Float => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExrCompression {
+    None,
+    #[default]
+    Zip,
+}
+
+impl ExrCompression {
+    fn code(&self) -> u8 {
+        match self {
+            ExrCompression::None => 0,
+            ExrCompression::Zip => 3,
+        }
+    }
+
+    //scanlines stored together in one chunk
+    fn lines_per_chunk(&self) -> usize {
+        match self {
+            ExrCompression::None => 1,
+            ExrCompression::Zip => 16,
+        }
+    }
+}
+
+struct ExrChannel {
+    name: String,
+    pixel_type: ExrPixelType,
+    values: Vec<f64>,
+}
+
+//channels collected for one file, all of the same size
+pub struct ExrImage {
+    width: usize,
+    height: usize,
+    channels: Vec<ExrChannel>,
+    pub compression: ExrCompression,
+    pub color_space: ColorSpace, //primaries of the rgb channels, stored as the chromaticities attribute
+}
+
+impl ExrImage {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            channels: Vec::new(),
+            compression: ExrCompression::default(),
+            color_space: ColorSpace::LinearSrgb,
+        }
+    }
+
+    //adds one channel per entry of `names`, taken from the red, green and blue components of
+    //`image` in order. channels are called `layer.name`, or just `name` for the empty layer
+    pub fn add_layer(&mut self, layer: &str, image: &Image, names: &[&str], pixel_type: ExrPixelType) -> io::Result<()> {
+        if image.width() != self.width || image.height() != self.height {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("layer '{}' has different dimensions than the image", layer)));
+        }
+        for (component, name) in names.iter().take(3).enumerate() {
+            let name = match layer {
+                "" => name.to_string(),
+                layer => format!("{}.{}", layer, name),
+            };
+            if self.channels.iter().any(|channel| channel.name == name) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("duplicate channel '{}'", name)));
+            }
+            let values = image.pixels().iter().map(|color| color.get(component)).collect();
+            self.channels.push(ExrChannel { name, pixel_type, values });
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, mut out: impl Write) -> io::Result<()> {
+        if self.width == 0 || self.height == 0 || self.channels.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "exr images need at least one pixel and one channel"));
+        }
+        //readers expect the channels sorted by name, both in the header and in the pixel data
+        let mut channels: Vec<&ExrChannel> = self.channels.iter().collect();
+        channels.sort_by(|a, b| a.name.as_bytes().cmp(b.name.as_bytes()));
+
+        let header = self.header(&channels);
+        let lines_per_chunk = self.compression.lines_per_chunk();
+        let chunks: Vec<Vec<u8>> = (0..self.height)
+            .step_by(lines_per_chunk)
+            .map(|y0| self.chunk(&channels, y0, (y0 + lines_per_chunk).min(self.height)))
+            .collect();
+
+        //offset table of absolute file positions, then the chunks themselves
+        out.write_all(&header)?;
+        let mut offset = (header.len() + 8*chunks.len()) as u64;
+        for chunk in &chunks {
+            out.write_all(&offset.to_le_bytes())?;
+            offset += chunk.len() as u64;
+        }
+        for chunk in &chunks {
+            out.write_all(chunk)?;
+        }
+        Ok(())
+    }
+
+    fn header(&self, channels: &[&ExrChannel]) -> Vec<u8> {
+        let long_names = channels.iter().any(|channel| channel.name.len() > 31);
+        let mut header = Vec::new();
+        header.extend_from_slice(&MAGIC.to_le_bytes());
+        header.extend_from_slice(&(VERSION | if long_names { LONG_NAMES_FLAG } else { 0 }).to_le_bytes());
+
+        let mut chlist = Vec::new();
+        for channel in channels {
+            chlist.extend_from_slice(channel.name.as_bytes());
+            chlist.push(0);
+            chlist.extend_from_slice(&channel.pixel_type.code().to_le_bytes());
+            //plinear and three reserved bytes, then x and y sampling
+            chlist.extend_from_slice(&[0, 0, 0, 0]);
+            chlist.extend_from_slice(&1_i32.to_le_bytes());
+            chlist.extend_from_slice(&1_i32.to_le_bytes());
+        }
+        chlist.push(0);
+
+        let mut window = Vec::new();
+        for v in [0, 0, self.width as i32 - 1, self.height as i32 - 1] {
+            window.extend_from_slice(&v.to_le_bytes());
+        }
+        let c = self.color_space.chromaticities();
+        let mut chromaticities = Vec::new();
+        for v in [c.red, c.green, c.blue, c.white].concat() {
+            chromaticities.extend_from_slice(&(v as f32).to_le_bytes());
+        }
+        let mut screen_center = Vec::new();
+        screen_center.extend_from_slice(&0_f32.to_le_bytes());
+        screen_center.extend_from_slice(&0_f32.to_le_bytes());
+
+        //the attributes every scanline file has to have
+        attribute(&mut header, "channels", "chlist", &chlist);
+        attribute(&mut header, "compression", "compression", &[self.compression.code()]);
+        attribute(&mut header, "dataWindow", "box2i", &window);
+        attribute(&mut header, "displayWindow", "box2i", &window);
+        attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        attribute(&mut header, "pixelAspectRatio", "float", &1_f32.to_le_bytes());
+        attribute(&mut header, "screenWindowCenter", "v2f", &screen_center);
+        attribute(&mut header, "screenWindowWidth", "float", &1_f32.to_le_bytes());
+        //optional, readers assume srgb primaries without it
+        attribute(&mut header, "chromaticities", "chromaticities", &chromaticities);
+        header.push(0);
+        header
+    }
+
+    //scanlines y0..y1, each line holding all values of the first channel, then the second, ...
+    fn chunk(&self, channels: &[&ExrChannel], y0: usize, y1: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        for y in y0..y1 {
+            for channel in channels {
+                for &v in &channel.values[y*self.width..(y + 1)*self.width] {
+                    match channel.pixel_type {
+                        ExrPixelType::Uint => data.extend_from_slice(&(v.max(0_f64) as u32).to_le_bytes()),
+                        ExrPixelType::Half => data.extend_from_slice(&f32_to_half(v as f32).to_le_bytes()),
+                        ExrPixelType::Float => data.extend_from_slice(&(v as f32).to_le_bytes()),
+                    }
+                }
+            }
+        }
+        debug_assert_eq!(data.len(), (y1 - y0)*self.width*channels.iter().map(|c| c.pixel_type.size()).sum::<usize>());
+
+        let data = match self.compression {
+            ExrCompression::None => data,
+            //a chunk that doesn't get smaller is stored as is, readers tell by its size
+            ExrCompression::Zip => Some(zip(&data)).filter(|packed| packed.len() < data.len()).unwrap_or(data),
+        };
+        let mut chunk = Vec::with_capacity(data.len() + 8);
+        chunk.extend_from_slice(&(y0 as i32).to_le_bytes());
+        chunk.extend_from_slice(&(data.len() as i32).to_le_bytes());
+        chunk.extend_from_slice(&data);
+        chunk
+    }
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+//exr zip: the bytes are split into even and odd halves, delta encoded and then deflated, which
+//puts the similar high bytes of neighbouring values next to each other
+fn zip(data: &[u8]) -> Vec<u8> {
+    let mut split: Vec<u8> = data.iter().step_by(2).chain(data.iter().skip(1).step_by(2)).copied().collect();
+    for i in (1..split.len()).rev() {
+        split[i] = split[i].wrapping_sub(split[i - 1]).wrapping_add(128);
+    }
+    miniz_oxide::deflate::compress_to_vec_zlib(&split, 6)
+}
+
+//rounds to the nearest half, ties to even. out of range values become infinity and nan stays nan
+pub fn f32_to_half(v: f32) -> u16 {
+    let bits = v.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if half_exponent > 0 {
+        //rounding can carry into the exponent, which is still the right answer up to infinity
+        let half = ((half_exponent as u32) << 10) | (mantissa >> 13);
+        return sign | round_shifted(half, mantissa & 0x1fff, 13) as u16;
+    }
+
+    //subnormal half, with the implicit leading one made explicit
+    let shift = (126 - exponent) as u32;
+    if shift > 24 {
+        return sign;
+    }
+    let mantissa = mantissa | 0x80_0000;
+    sign | round_shifted(mantissa >> shift, mantissa & ((1 << shift) - 1), shift) as u16
+}
+
+//`value` was shifted right by `shift` bits with `rest` being the bits that fell off
+fn round_shifted(value: u32, rest: u32, shift: u32) -> u32 {
+    let halfway = 1 << (shift - 1);
+    match rest > halfway || (rest == halfway && value & 1 == 1) {
+        true => value + 1,
+        false => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+
+    #[test]
+    fn half_conversion_of_normal_values() {
+        assert_eq!(f32_to_half(0_f32), 0);
+        assert_eq!(f32_to_half(-0_f32), 0x8000);
+        assert_eq!(f32_to_half(1_f32), 0x3c00);
+        assert_eq!(f32_to_half(-2_f32), 0xc000);
+        assert_eq!(f32_to_half(0.5), 0x3800);
+        assert_eq!(f32_to_half(65504_f32), 0x7bff);
+    }
+
+    #[test]
+    fn half_conversion_rounds_to_nearest_even() {
+        let ulp = 2_f32.powi(-10);
+        //halfway cases go to the even mantissa, anything past halfway rounds up
+        assert_eq!(f32_to_half(1_f32 + 0.5*ulp), 0x3c00);
+        assert_eq!(f32_to_half(1_f32 + 1.5*ulp), 0x3c02);
+        assert_eq!(f32_to_half(1_f32 + 0.5*ulp + 2_f32.powi(-20)), 0x3c01);
+        assert_eq!(f32_to_half(1_f32 + 0.49*ulp), 0x3c00);
+        //rounding up the largest mantissa carries into the exponent
+        assert_eq!(f32_to_half(2_f32 - 0.25*ulp), 0x4000);
+    }
+
+    #[test]
+    fn half_conversion_of_subnormals() {
+        let smallest = 2_f32.powi(-24);
+        assert_eq!(f32_to_half(smallest), 0x0001);
+        assert_eq!(f32_to_half(-smallest), 0x8001);
+        assert_eq!(f32_to_half(2_f32.powi(-14) - smallest), 0x03ff);
+        assert_eq!(f32_to_half(2_f32.powi(-14)), 0x0400);
+        //halfway between 0 and the smallest subnormal rounds to even zero, a bit more rounds up
+        assert_eq!(f32_to_half(0.5*smallest), 0);
+        assert_eq!(f32_to_half(0.75*smallest), 0x0001);
+        assert_eq!(f32_to_half(1.5*smallest), 0x0002);
+        assert_eq!(f32_to_half(1e-10), 0);
+        assert_eq!(f32_to_half(f32::MIN_POSITIVE), 0);
+    }
+
+    #[test]
+    fn half_conversion_of_overflow_infinity_and_nan() {
+        assert_eq!(f32_to_half(65519_f32), 0x7bff);
+        assert_eq!(f32_to_half(65520_f32), 0x7c00);
+        assert_eq!(f32_to_half(1e10), 0x7c00);
+        assert_eq!(f32_to_half(-1e10), 0xfc00);
+        assert_eq!(f32_to_half(f32::INFINITY), 0x7c00);
+        assert_eq!(f32_to_half(f32::NEG_INFINITY), 0xfc00);
+        let nan = f32_to_half(f32::NAN);
+        assert_eq!(nan & 0x7c00, 0x7c00);
+        assert_ne!(nan & 0x3ff, 0);
+    }
+
+    fn image(width: usize, height: usize) -> Image {
+        let mut image = Image::new(width, height);
+        for (i, pixel) in image.pixels_mut().iter_mut().enumerate() {
+            *pixel = Color::new(i as f64, 0.5, -1_f64);
+        }
+        image
+    }
+
+    fn write(exr: &ExrImage) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        exr.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    //byte offset just past the attribute called `name`, which has to exist
+    fn attribute_end(bytes: &[u8], name: &str) -> usize {
+        let mut key = name.as_bytes().to_vec();
+        key.push(0);
+        let start = bytes.windows(key.len()).position(|w| w == key).unwrap_or_else(|| panic!("no {} attribute", name));
+        let kind_end = start + key.len() + bytes[start + key.len()..].iter().position(|&b| b == 0).unwrap() + 1;
+        let size = i32::from_le_bytes(bytes[kind_end..kind_end + 4].try_into().unwrap()) as usize;
+        kind_end + 4 + size
+    }
+
+    #[test]
+    fn header_bytes() {
+        let mut exr = ExrImage::new(2, 1);
+        exr.compression = ExrCompression::None;
+        exr.add_layer("", &image(2, 1), &["R"], ExrPixelType::Float).unwrap();
+        let bytes = write(&exr);
+
+        assert_eq!(bytes[..8], [0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+        let mut chlist = b"channels\0chlist\0".to_vec();
+        chlist.extend_from_slice(&19_i32.to_le_bytes());
+        chlist.extend_from_slice(b"R\0");
+        chlist.extend_from_slice(&[2, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(bytes[8..8 + chlist.len()], chlist);
+
+        let mut window = b"dataWindow\0box2i\0".to_vec();
+        window.extend_from_slice(&16_i32.to_le_bytes());
+        for v in [0_i32, 0, 1, 0] {
+            window.extend_from_slice(&v.to_le_bytes());
+        }
+        assert!(bytes.windows(window.len()).any(|w| w == window));
+        assert_eq!(bytes[attribute_end(&bytes, "compression") - 1], 0);
+
+        //the header ends after the chromaticities, then one offset for the single scanline
+        let header_end = attribute_end(&bytes, "chromaticities") + 1;
+        assert_eq!(bytes[header_end - 1], 0);
+        let offset = u64::from_le_bytes(bytes[header_end..header_end + 8].try_into().unwrap()) as usize;
+        assert_eq!(offset, header_end + 8);
+        let mut chunk = 0_i32.to_le_bytes().to_vec();
+        chunk.extend_from_slice(&8_i32.to_le_bytes());
+        chunk.extend_from_slice(&0_f32.to_le_bytes());
+        chunk.extend_from_slice(&1_f32.to_le_bytes());
+        assert_eq!(bytes[offset..], chunk);
+    }
+
+    #[test]
+    fn chromaticities_follow_the_color_space() {
+        let mut exr = ExrImage::new(1, 1);
+        exr.color_space = ColorSpace::AcesCg;
+        exr.add_layer("", &image(1, 1), &["R"], ExrPixelType::Half).unwrap();
+        let bytes = write(&exr);
+        let end = attribute_end(&bytes, "chromaticities");
+        let c = ColorSpace::AcesCg.chromaticities();
+        let expected: Vec<u8> = [c.red, c.green, c.blue, c.white].concat().iter().flat_map(|&v| (v as f32).to_le_bytes()).collect();
+        assert_eq!(bytes[end - 32..end], expected);
+    }
+
+    #[test]
+    fn channels_are_sorted_and_layered() {
+        let mut exr = ExrImage::new(1, 1);
+        exr.add_layer("", &image(1, 1), &["R", "G", "B"], ExrPixelType::Half).unwrap();
+        exr.add_layer("albedo", &image(1, 1), &["R", "G", "B"], ExrPixelType::Half).unwrap();
+        let bytes = write(&exr);
+        let names: Vec<&[u8]> = ["B", "G", "R", "albedo.B", "albedo.G", "albedo.R"].iter().map(|n| n.as_bytes()).collect();
+        let positions: Vec<usize> = names.iter()
+            .map(|name| bytes.windows(name.len() + 5).position(|w| w[..name.len()] == **name && w[name.len()..] == [0, 1, 0, 0, 0]).unwrap())
+            .collect();
+        assert!(positions.windows(2).all(|p| p[0] < p[1]), "{:?}", positions);
+    }
+
+    #[test]
+    fn long_channel_names_set_the_flag() {
+        let mut exr = ExrImage::new(1, 1);
+        exr.add_layer("a_layer_name_long_enough_to_overflow", &image(1, 1), &["R"], ExrPixelType::Half).unwrap();
+        assert_eq!(u32::from_le_bytes(write(&exr)[4..8].try_into().unwrap()), VERSION | LONG_NAMES_FLAG);
+    }
+
+    #[test]
+    fn zip_chunks_decompress_to_the_pixels() {
+        let (width, height) = (64, 20);
+        let mut exr = ExrImage::new(width, height);
+        exr.add_layer("", &image(width, height), &["R", "G"], ExrPixelType::Float).unwrap();
+        let bytes = write(&exr);
+        let header_end = attribute_end(&bytes, "chromaticities") + 1;
+        let offsets: Vec<usize> = (0..2)
+            .map(|i| u64::from_le_bytes(bytes[header_end + 8*i..header_end + 8*(i + 1)].try_into().unwrap()) as usize)
+            .collect();
+
+        for (chunk, &offset) in offsets.iter().enumerate() {
+            let y0 = i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize;
+            let size = i32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap()) as usize;
+            assert_eq!(y0, 16*chunk);
+            let lines = (height - y0).min(16);
+            let raw_size = lines*width*2*4;
+            assert!(size < raw_size);
+
+            //undo the deflate, the delta encoding and the byte split
+            let mut split = miniz_oxide::inflate::decompress_to_vec_zlib(&bytes[offset + 8..offset + 8 + size]).unwrap();
+            assert_eq!(split.len(), raw_size);
+            for i in 1..split.len() {
+                split[i] = split[i].wrapping_add(split[i - 1]).wrapping_sub(128);
+            }
+            let (even, odd) = split.split_at(raw_size.div_ceil(2));
+            let data: Vec<u8> = (0..raw_size).map(|i| if i % 2 == 0 { even[i/2] } else { odd[i/2] }).collect();
+
+            let value = |i: usize| f32::from_le_bytes(data[4*i..4*i + 4].try_into().unwrap());
+            for line in 0..lines {
+                for x in 0..width {
+                    //green comes before red within each scanline
+                    assert_eq!(value(line*2*width + x), 0.5);
+                    assert_eq!(value(line*2*width + width + x), ((y0 + line)*width + x) as f32);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_mismatched_and_duplicate_layers() {
+        let mut exr = ExrImage::new(2, 2);
+        assert!(exr.write(Vec::new()).is_err());
+        assert!(exr.add_layer("", &image(2, 1), &["R"], ExrPixelType::Half).is_err());
+        exr.add_layer("", &image(2, 2), &["R"], ExrPixelType::Half).unwrap();
+        assert!(exr.add_layer("", &image(2, 2), &["R"], ExrPixelType::Float).is_err());
+    }
+}
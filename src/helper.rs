@@ -1,4 +1,4 @@
-use rand::Rng;
+use rand::{rngs::StdRng, Rng as _, SeedableRng};
 
 pub const INFINITY: f64 = f64::INFINITY;
 pub const PI: f64 = std::f64::consts::PI;
@@ -7,13 +7,53 @@ pub fn deg_to_rad(degrees: f64) -> f64 {
     degrees * PI / 180_f64
 }
 
-pub fn random_f64() -> f64 {
-    let mut rng = rand::thread_rng();
-    rng.r#gen::<f64>()
+//explicitly seeded random number generator. everything random in scene setup and rendering
+//draws from one of these so a given seed always produces the same image
+pub struct Rng {
+    inner: StdRng,
 }
 
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            inner: StdRng::seed_from_u64(seed),
+        }
+    }
 
-pub fn random_f64_range(min: f64, max: f64) -> f64 {
-    let mut rng = rand::thread_rng();
-    min + (max - min) * rng.r#gen::<f64>()
+    //independent stream for one sample of one pixel, so results don't depend on which thread
+    //renders it or on how many samples were taken before
+    pub fn for_pixel_sample(seed: u64, x: i32, y: i32, index: u32) -> Self {
+        Self::new(hash(&[seed, x as u64, y as u64, index as u64]))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.inner.r#gen::<u64>()
+    }
+}
+
+//splitmix64 finalizer, spreads nearby seeds far apart
+pub fn mix_bits(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+//combines several values into one well mixed 64-bit hash
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x51_7c_c1_b7_27_22_0a_95, |h, &v| mix_bits(h ^ mix_bits(v)))
+}
+
+//uniform value in [0, 1) derived deterministically from a hash
+pub fn hash_to_f64(h: u64) -> f64 {
+    (h >> 11) as f64 * (1_f64/(1_u64 << 53) as f64)
+}
+
+pub fn random_f64(rng: &mut Rng) -> f64 {
+    rng.inner.r#gen::<f64>()
+}
+
+
+pub fn random_f64_range(rng: &mut Rng, min: f64, max: f64) -> f64 {
+    min + (max - min) * rng.inner.r#gen::<f64>()
 }
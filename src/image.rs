@@ -0,0 +1,87 @@
+use crate::color::Color;
+
+//how texel lookups outside the image are mapped back into it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    fn apply(&self, i: i64, n: usize) -> usize {
+        let n = n as i64;
+        let wrapped = match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Clamp => i.clamp(0, n - 1),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2*n);
+                if m >= n { 2*n - 1 - m } else { m }
+            }
+        };
+        wrapped as usize
+    }
+}
+
+//framebuffer of linear (unclamped, un-gamma-corrected) pixel colors, stored row by row from the top left
+#[derive(Clone)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::new_empty(); width*height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y*self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Color) {
+        self.pixels[y*self.width + x] = color;
+    }
+
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [Color] {
+        &mut self.pixels
+    }
+
+    //bilinearly filtered lookup at continuous pixel coordinates, where pixel (x, y) covers
+    //[x, x+1) x [y, y+1). black for an empty image
+    pub fn sample_bilinear(&self, x: f64, y: f64, wrap_x: WrapMode, wrap_y: WrapMode) -> Color {
+        if self.width == 0 || self.height == 0 {
+            return Color::new_empty();
+        }
+        let fx = x - 0.5;
+        let fy = y - 0.5;
+        let (x0, y0) = (fx.floor(), fy.floor());
+        let (tx, ty) = (fx - x0, fy - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let x1 = wrap_x.apply(x0 + 1, self.width);
+        let y1 = wrap_y.apply(y0 + 1, self.height);
+        let x0 = wrap_x.apply(x0, self.width);
+        let y0 = wrap_y.apply(y0, self.height);
+
+        let top = (1_f64 - tx)*self.get(x0, y0) + tx*self.get(x1, y0);
+        let bottom = (1_f64 - tx)*self.get(x0, y1) + tx*self.get(x1, y1);
+        (1_f64 - ty)*top + ty*bottom
+    }
+}
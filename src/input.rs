@@ -0,0 +1,557 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use crate::{color::{srgb_to_linear, Color}, image::Image};
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io { path: PathBuf, source: io::Error },
+    Format { path: PathBuf, message: String },
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ImageError::Format { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl Error for ImageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImageError::Io { source, .. } => Some(source),
+            ImageError::Format { .. } => None,
+        }
+    }
+}
+
+fn format_error(path: &Path, message: impl Into<String>) -> ImageError {
+    ImageError::Format { path: path.to_path_buf(), message: message.into() }
+}
+
+//largest width or height and pixel count taken from a file header, a 16k x 4k environment map
+//fits. anything bigger is far more likely a corrupt header than a real image
+const MAX_DIMENSION: usize = 1 << 16;
+const MAX_PIXELS: usize = 1 << 26;
+
+//rejects empty and implausibly large images before anything gets allocated for them
+fn check_dimensions(path: &Path, width: usize, height: usize) -> Result<usize, ImageError> {
+    if width == 0 || height == 0 {
+        return Err(format_error(path, format!("empty {}x{} image", width, height)));
+    }
+    match width.checked_mul(height) {
+        Some(pixels) if width <= MAX_DIMENSION && height <= MAX_DIMENSION && pixels <= MAX_PIXELS => Ok(pixels),
+        _ => Err(format_error(path, format!("implausibly large {}x{} image", width, height))),
+    }
+}
+
+fn read_bytes(path: &Path) -> Result<Vec<u8>, ImageError> {
+    fs::read(path).map_err(|source| ImageError::Io { path: path.to_path_buf(), source })
+}
+
+fn extension(path: &Path) -> Option<String> {
+    path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase())
+}
+
+//reads any supported image as linear colors, picking the format from the file extension.
+//8 and 16-bit formats are assumed to be srgb encoded
+pub fn read_image(path: impl AsRef<Path>) -> Result<Image, ImageError> {
+    let path = path.as_ref();
+    match extension(path).as_deref() {
+        Some("hdr" | "pic" | "pfm") => read_hdr_image(path),
+        _ => {
+            let mut image = read_ldr_image(path)?;
+            for pixel in image.pixels_mut() {
+                *pixel = Color::new(srgb_to_linear(pixel.x()), srgb_to_linear(pixel.y()), srgb_to_linear(pixel.z()));
+            }
+            Ok(image)
+        }
+    }
+}
+
+//reads a low dynamic range image with its stored (still encoded) values scaled to [0, 1]
+pub fn read_ldr_image(path: impl AsRef<Path>) -> Result<Image, ImageError> {
+    let path = path.as_ref();
+    match extension(path).as_deref() {
+        Some("png") => read_png(path),
+        Some("jpg" | "jpeg") => read_jpeg(path),
+        Some("ppm" | "pgm" | "pnm") => read_pnm(path),
+        _ => Err(format_error(path, "unsupported image format, expected .png, .jpg or .ppm")),
+    }
+}
+
+//reads a high dynamic range image, picking the format from the file extension
+pub fn read_hdr_image(path: impl AsRef<Path>) -> Result<Image, ImageError> {
+    let path = path.as_ref();
+    match extension(path).as_deref() {
+        Some("hdr" | "pic") => read_rgbe(path),
+        Some("pfm") => read_pfm(path),
+        _ => Err(format_error(path, "unsupported hdr image format, expected .hdr or .pfm")),
+    }
+}
+
+//cursor over the raw file bytes used by the header parsers
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn line(&mut self) -> Option<&'a str> {
+        if self.pos >= self.data.len() {
+            return None;
+        }
+        let rest = &self.data[self.pos..];
+        let len = rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len());
+        self.pos += (len + 1).min(rest.len());
+        std::str::from_utf8(&rest[..len]).ok()
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        let b = self.data.get(self.pos).copied();
+        self.pos += 1;
+        b
+    }
+
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let slice = self.data.get(self.pos..self.pos + n);
+        self.pos += n;
+        slice
+    }
+}
+
+//radiance rgbe (.hdr), flat or with per-scanline run length encoding
+pub fn read_rgbe(path: impl AsRef<Path>) -> Result<Image, ImageError> {
+    let path = path.as_ref();
+    let data = read_bytes(path)?;
+    let mut reader = Reader { data: &data, pos: 0 };
+
+    match reader.line() {
+        Some(magic) if magic.starts_with("#?") => {}
+        _ => return Err(format_error(path, "missing radiance header")),
+    }
+    loop {
+        match reader.line() {
+            None => return Err(format_error(path, "unterminated radiance header")),
+            Some("") => break,
+            Some(line) if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" => {
+                return Err(format_error(path, format!("unsupported pixel format '{}'", &line[7..])));
+            }
+            Some(_) => {}
+        }
+    }
+
+    let resolution = reader.line().unwrap_or("");
+    let (flip_y, height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        [y_dir @ ("-Y" | "+Y"), h, "+X", w] => match (h.parse::<usize>(), w.parse::<usize>()) {
+            (Ok(h), Ok(w)) => (y_dir == "+Y", h, w),
+            _ => return Err(format_error(path, format!("invalid resolution line '{}'", resolution))),
+        },
+        _ => return Err(format_error(path, format!("unsupported resolution line '{}'", resolution))),
+    };
+
+    check_dimensions(path, width, height)?;
+
+    //scanlines are decoded before the image is allocated, so a truncated file fails early
+    let invalid = || format_error(path, "invalid or truncated pixel data");
+    let mut rows = Vec::new();
+    let mut scanline = vec![[0_u8; 4]; width];
+    for _ in 0..height {
+        read_rgbe_scanline(&mut reader, &mut scanline).ok_or_else(invalid)?;
+        rows.extend_from_slice(&scanline);
+    }
+    let mut image = Image::new(width, height);
+    for (row, scanline) in rows.chunks_exact(width).enumerate() {
+        let y = if flip_y { height - 1 - row } else { row };
+        for (x, rgbe) in scanline.iter().enumerate() {
+            image.set(x, y, rgbe_to_color(rgbe));
+        }
+    }
+    Ok(image)
+}
+
+fn read_rgbe_scanline(reader: &mut Reader, scanline: &mut [[u8; 4]]) -> Option<()> {
+    let width = scanline.len();
+    let head = reader.bytes(4)?;
+    let is_rle = (8..0x8000).contains(&width) && head[0] == 2 && head[1] == 2 && head[2] & 0x80 == 0;
+    if !is_rle {
+        //flat pixels, where (1, 1, 1, n) repeats the previous pixel
+        let mut x = 0;
+        let mut shift = 0;
+        let mut pixel = [head[0], head[1], head[2], head[3]];
+        loop {
+            if pixel[0] == 1 && pixel[1] == 1 && pixel[2] == 1 && x > 0 {
+                //consecutive runs make up the higher bytes of one long count
+                let count = (pixel[3] as usize).checked_shl(shift)?;
+                let prev = scanline[x - 1];
+                for _ in 0..count.min(width - x) {
+                    scanline[x] = prev;
+                    x += 1;
+                }
+                shift += 8;
+            } else {
+                scanline[x] = pixel;
+                x += 1;
+                shift = 0;
+            }
+            if x >= width {
+                return Some(());
+            }
+            let next = reader.bytes(4)?;
+            pixel = [next[0], next[1], next[2], next[3]];
+        }
+    }
+
+    if ((head[2] as usize) << 8 | head[3] as usize) != width {
+        return None;
+    }
+    //each channel is stored separately as runs (count > 128) or literal spans
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = reader.byte()? as usize;
+            if count > 128 {
+                let count = count - 128;
+                let value = reader.byte()?;
+                for pixel in scanline.get_mut(x..x + count)? {
+                    pixel[channel] = value;
+                }
+                x += count;
+            } else if count > 0 {
+                let values = reader.bytes(count)?;
+                for (pixel, &value) in scanline.get_mut(x..x + count)?.iter_mut().zip(values) {
+                    pixel[channel] = value;
+                }
+                x += count;
+            } else {
+                return None;
+            }
+        }
+    }
+    Some(())
+}
+
+fn rgbe_to_color(rgbe: &[u8; 4]) -> Color {
+    if rgbe[3] == 0 {
+        return Color::new_empty();
+    }
+    let scale = 2_f64.powi(rgbe[3] as i32 - (128 + 8));
+    Color::new(
+        (rgbe[0] as f64 + 0.5)*scale,
+        (rgbe[1] as f64 + 0.5)*scale,
+        (rgbe[2] as f64 + 0.5)*scale,
+    )
+}
+
+//portable float map, color ("PF") or greyscale ("Pf"). rows are stored bottom to top
+pub fn read_pfm(path: impl AsRef<Path>) -> Result<Image, ImageError> {
+    let path = path.as_ref();
+    let data = read_bytes(path)?;
+    let mut reader = Reader { data: &data, pos: 0 };
+
+    let channels = match reader.line().map(str::trim) {
+        Some("PF") => 3,
+        Some("Pf") => 1,
+        _ => return Err(format_error(path, "missing PF/Pf header")),
+    };
+    let dims = reader.line().unwrap_or("");
+    let (width, height) = match dims.split_whitespace().map(str::parse::<usize>).collect::<Vec<_>>()[..] {
+        [Ok(w), Ok(h)] => (w, h),
+        _ => return Err(format_error(path, format!("invalid dimensions '{}'", dims))),
+    };
+    let scale = reader.line().and_then(|l| l.trim().parse::<f64>().ok())
+        .ok_or_else(|| format_error(path, "invalid scale"))?;
+    let little_endian = scale < 0_f64;
+
+    let size = check_dimensions(path, width, height)?*channels*4;
+    let floats = reader.bytes(size).ok_or_else(|| format_error(path, "unexpected end of pixel data"))?;
+    let value = |i: usize| {
+        let bytes = [floats[4*i], floats[4*i + 1], floats[4*i + 2], floats[4*i + 3]];
+        (if little_endian { f32::from_le_bytes(bytes) } else { f32::from_be_bytes(bytes) }) as f64
+    };
+
+    let mut image = Image::new(width, height);
+    for row in 0..height {
+        for x in 0..width {
+            let i = (row*width + x)*channels;
+            let color = match channels {
+                3 => Color::new(value(i), value(i + 1), value(i + 2)),
+                _ => Color::new(value(i), value(i), value(i)),
+            };
+            image.set(x, height - 1 - row, color);
+        }
+    }
+    Ok(image)
+}
+
+//builds an image from interleaved samples, using the first three channels as rgb (or the
+//first as grey when there are fewer than three). extra channels such as alpha are dropped
+fn image_from_samples(width: usize, height: usize, channels: usize, sample: impl Fn(usize) -> f64) -> Image {
+    let mut image = Image::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y*width + x)*channels;
+            let color = match channels {
+                1 | 2 => Color::new(sample(i), sample(i), sample(i)),
+                _ => Color::new(sample(i), sample(i + 1), sample(i + 2)),
+            };
+            image.set(x, y, color);
+        }
+    }
+    image
+}
+
+pub fn read_png(path: impl AsRef<Path>) -> Result<Image, ImageError> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|source| ImageError::Io { path: path.to_path_buf(), source })?;
+    let png_error = |e: png::DecodingError| format_error(path, e.to_string());
+
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    //expand palettes and low bit depth greyscale so every sample is 8 or 16 bits
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(png_error)?;
+    let mut buf = vec![0_u8; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(png_error)?;
+
+    let channels = info.color_type.samples();
+    let (width, height) = (info.width as usize, info.height as usize);
+    check_dimensions(path, width, height)?;
+    let image = match info.bit_depth {
+        png::BitDepth::Sixteen => image_from_samples(width, height, channels, |i| {
+            u16::from_be_bytes([buf[2*i], buf[2*i + 1]]) as f64/65535_f64
+        }),
+        _ => image_from_samples(width, height, channels, |i| buf[i] as f64/255_f64),
+    };
+    Ok(image)
+}
+
+pub fn read_jpeg(path: impl AsRef<Path>) -> Result<Image, ImageError> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|source| ImageError::Io { path: path.to_path_buf(), source })?;
+
+    let mut decoder = jpeg_decoder::Decoder::new(BufReader::new(file));
+    let data = decoder.decode().map_err(|e| format_error(path, e.to_string()))?;
+    let info = decoder.info().ok_or_else(|| format_error(path, "missing jpeg frame header"))?;
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    check_dimensions(path, width, height)?;
+    let image = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => image_from_samples(width, height, 1, |i| data[i] as f64/255_f64),
+        jpeg_decoder::PixelFormat::L16 => image_from_samples(width, height, 1, |i| {
+            u16::from_ne_bytes([data[2*i], data[2*i + 1]]) as f64/65535_f64
+        }),
+        jpeg_decoder::PixelFormat::RGB24 => image_from_samples(width, height, 3, |i| data[i] as f64/255_f64),
+        //adobe style inverted cmyk, converted naively without a color profile
+        jpeg_decoder::PixelFormat::CMYK32 => image_from_samples(width, height, 3, |i| {
+            let pixel = i/3*4;
+            let k = data[pixel + 3] as f64/255_f64;
+            data[pixel + i % 3] as f64/255_f64*k
+        }),
+    };
+    Ok(image)
+}
+
+//binary (P5/P6) or ascii (P2/P3) greymap/pixmap with any maxval
+pub fn read_pnm(path: impl AsRef<Path>) -> Result<Image, ImageError> {
+    let path = path.as_ref();
+    let data = read_bytes(path)?;
+
+    //the header is whitespace separated tokens, with comments running to the end of a line
+    let mut pos = 0;
+    let mut header = Vec::with_capacity(4);
+    while header.len() < 4 && pos < data.len() {
+        match data[pos] {
+            b'#' => {
+                while pos < data.len() && data[pos] != b'\n' { pos += 1; }
+            }
+            b if b.is_ascii_whitespace() => pos += 1,
+            _ => {
+                let start = pos;
+                while pos < data.len() && !data[pos].is_ascii_whitespace() { pos += 1; }
+                header.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+            }
+        }
+    }
+    //exactly one whitespace byte separates the header from binary pixel data
+    pos += 1;
+
+    let invalid = || format_error(path, "invalid pnm header");
+    if header.len() < 4 {
+        return Err(invalid());
+    }
+    let (channels, binary) = match header[0].as_str() {
+        "P2" => (1, false),
+        "P3" => (3, false),
+        "P5" => (1, true),
+        "P6" => (3, true),
+        magic => return Err(format_error(path, format!("unsupported pnm type '{}'", magic))),
+    };
+    let width: usize = header[1].parse().map_err(|_| invalid())?;
+    let height: usize = header[2].parse().map_err(|_| invalid())?;
+    let maxval: u32 = header[3].parse().map_err(|_| invalid())?;
+    if maxval == 0 || maxval > 65535 {
+        return Err(invalid());
+    }
+
+    let count = check_dimensions(path, width, height)?*channels;
+    let samples: Vec<u32> = match (binary, maxval > 255) {
+        (true, false) => data.get(pos..pos + count).map(|b| b.iter().map(|&v| v as u32).collect()),
+        (true, true) => data.get(pos..pos + 2*count)
+            .map(|b| b.chunks_exact(2).map(|v| u16::from_be_bytes([v[0], v[1]]) as u32).collect()),
+        (false, _) => {
+            let text = String::from_utf8_lossy(data.get(pos.min(data.len())..).unwrap_or(&[]));
+            let values: Vec<u32> = text.split_ascii_whitespace().take(count).filter_map(|t| t.parse().ok()).collect();
+            Some(values).filter(|v| v.len() == count)
+        }
+    }.ok_or_else(|| format_error(path, "unexpected end of pixel data"))?;
+
+    Ok(image_from_samples(width, height, channels, |i| samples[i] as f64/maxval as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{colorspace::ColorSpace, output::{write_pfm, write_png, write_ppm, BitDepth}, test_util::TempPath};
+
+    //writes `bytes` to a temp file and decodes it
+    fn decode(name: &str, bytes: &[u8]) -> Result<Image, ImageError> {
+        let path = TempPath::new(&format!("input-{}", name));
+        fs::write(&path, bytes).unwrap();
+        read_image(&path)
+    }
+
+    fn encode(name: &str, write: impl FnOnce(&mut Vec<u8>)) -> Result<Image, ImageError> {
+        let mut bytes = Vec::new();
+        write(&mut bytes);
+        decode(name, &bytes)
+    }
+
+    fn assert_format_error(result: Result<Image, ImageError>) {
+        match result {
+            Err(ImageError::Format { .. }) => {}
+            Err(e) => panic!("expected a format error, got {}", e),
+            Ok(image) => panic!("expected a format error, got a {}x{} image", image.width(), image.height()),
+        }
+    }
+
+    fn rgb(color: &Color) -> [f64; 3] {
+        [color.x(), color.y(), color.z()]
+    }
+
+    fn assert_close(a: &Image, b: &Image, tolerance: f64) {
+        assert_eq!((a.width(), a.height()), (b.width(), b.height()));
+        for (p, q) in a.pixels().iter().zip(b.pixels()) {
+            for c in 0..3 {
+                assert!((p.get(c) - q.get(c)).abs() <= tolerance, "{:?} != {:?}", p, q);
+            }
+        }
+    }
+
+    //3x2 image of colors whose srgb encodings land exactly on 8-bit codes
+    fn test_image() -> Image {
+        let code = |k: u32| srgb_to_linear(k as f64/255_f64);
+        let mut image = Image::new(3, 2);
+        for (i, pixel) in image.pixels_mut().iter_mut().enumerate() {
+            let k = i as u32*40;
+            *pixel = Color::new(code(k), code(255 - k), code((k*7) % 256));
+        }
+        image
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        let mut image = test_image();
+        image.set(2, 1, Color::new(1000.5, 0.001, 12.25));
+        let decoded = encode("round-trip.pfm", |out| write_pfm(&image, out).unwrap()).unwrap();
+        assert_close(&decoded, &image, 1e-6);
+        assert_eq!(decoded.get(2, 1).x(), 1000.5);
+    }
+
+    #[test]
+    fn greyscale_big_endian_pfm() {
+        let mut bytes = b"Pf\n2 1\n1.0\n".to_vec();
+        for v in [0.25_f32, 4_f32] {
+            bytes.extend_from_slice(&v.to_be_bytes());
+        }
+        let image = decode("grey.pfm", &bytes).unwrap();
+        assert_eq!(rgb(&image.get(0, 0)), [0.25; 3]);
+        assert_eq!(rgb(&image.get(1, 0)), [4_f64; 3]);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let image = test_image();
+        for (name, bit_depth) in [("round-trip-8.png", BitDepth::Eight), ("round-trip-16.png", BitDepth::Sixteen)] {
+            let decoded = encode(name, |out| write_png(&image, None, bit_depth, ColorSpace::LinearSrgb, false, out).unwrap()).unwrap();
+            assert_close(&decoded, &image, 1e-4);
+        }
+    }
+
+    #[test]
+    fn ppm_round_trip() {
+        let image = test_image();
+        let decoded = encode("round-trip.ppm", |out| write_ppm(&image, false, out).unwrap()).unwrap();
+        assert_close(&decoded, &image, 1e-9);
+    }
+
+    #[test]
+    fn binary_pnm_with_wide_samples() {
+        let mut bytes = b"P5\n# comment\n2 1\n1000\n".to_vec();
+        for v in [0_u16, 1000] {
+            bytes.extend_from_slice(&v.to_be_bytes());
+        }
+        let path = TempPath::new("input-wide.pgm");
+        fs::write(&path, &bytes).unwrap();
+        let image = read_ldr_image(&path).unwrap();
+        assert_eq!(rgb(&image.get(0, 0)), [0_f64; 3]);
+        assert_eq!(rgb(&image.get(1, 0)), [1_f64; 3]);
+    }
+
+    #[test]
+    fn flat_and_run_length_encoded_rgbe() {
+        let expected = [128.5/128_f64, 64.5/128_f64, 32.5/128_f64];
+        let header = |width: usize| format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X {}\n", width).into_bytes();
+
+        //one literal pixel followed by a run repeating it twice
+        let mut flat = header(3);
+        flat.extend_from_slice(&[128, 64, 32, 129, 1, 1, 1, 2]);
+        let image = decode("flat.hdr", &flat).unwrap();
+        assert!(image.pixels().iter().all(|pixel| rgb(pixel) == expected));
+
+        //each channel as a single run over the whole scanline
+        let mut rle = header(8);
+        rle.extend_from_slice(&[2, 2, 0, 8]);
+        for value in [128, 64, 32, 129] {
+            rle.extend_from_slice(&[128 + 8, value]);
+        }
+        let image = decode("rle.hdr", &rle).unwrap();
+        assert_eq!(image.width(), 8);
+        assert!(image.pixels().iter().all(|pixel| rgb(pixel) == expected));
+    }
+
+    #[test]
+    fn rejects_empty_images() {
+        assert_format_error(decode("empty.pfm", b"PF\n0 4\n-1.0\n"));
+        assert_format_error(decode("empty.ppm", b"P6\n4 0\n255\n"));
+        assert_format_error(decode("empty.hdr", b"#?RADIANCE\n\n-Y 0 +X 4\n"));
+    }
+
+    #[test]
+    fn rejects_huge_headers() {
+        assert_format_error(decode("huge.pfm", b"PF\n100000 100000\n-1.0\n"));
+        assert_format_error(decode("huge.ppm", b"P6\n70000 70000\n255\n"));
+        assert_format_error(decode("huge.hdr", b"#?RADIANCE\n\n-Y 65536 +X 65536\n"));
+    }
+
+    #[test]
+    fn rejects_truncated_pixel_data() {
+        assert_format_error(decode("truncated.pfm", b"PF\n2 2\n-1.0\n\0\0\0\0"));
+        assert_format_error(decode("truncated.ppm", b"P3\n2 1\n255\n1 2 3 4\n"));
+        assert_format_error(decode("truncated.hdr", b"#?RADIANCE\n\n-Y 2 +X 8\n\x02\x02\x00\x08\x88"));
+    }
+}
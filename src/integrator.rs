@@ -0,0 +1,320 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::{aov::id_color, background::Background, color::Color, helper::INFINITY, interval::Interval, light::{power_heuristic, LightList, LightSample}, onb::Onb, ray::{HitRecord, Hittable, Ray, Scatter}, sampler::{bounce_dimension, Sampler, LIGHT_DIMENSION_OFFSET, ROULETTE_DIMENSION_OFFSET}, vec3::{dot, random_cosine_direction, unit_vector}};
+
+//everything an integrator can look at while rendering, handed over by the camera per sample
+pub struct Scene<'a> {
+    pub world: &'a dyn Hittable,
+    pub lights: &'a LightList,
+    pub background: &'a dyn Background,
+    pub max_depth: i32, //hard limit on bounces for integrators that follow rays further
+    pub transparent_background: bool, //camera rays that miss everything see black instead of the background
+}
+
+impl Scene<'_> {
+    //radiance arriving along a ray that left the scene after `bounce` bounces. a transparent
+    //background leaves the pixels premultiplied by their coverage, as alpha channels expect
+    pub fn escaped(&self, ray: &Ray, bounce: u32) -> Color {
+        match self.transparent_background && bounce == 0 {
+            true => Color::new_empty(),
+            false => self.background.color(&ray.dir()),
+        }
+    }
+}
+
+//light transport algorithm, turning a camera ray into the color seen along it
+pub trait Integrator: Send + Sync {
+    fn li(&self, ray: Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color;
+}
+
+//the integrators selectable by name, e.g. from the command line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntegratorKind {
+    Path,
+    Whitted,
+    AmbientOcclusion,
+    Normals,
+    Depth,
+    Uv,
+    MaterialId,
+}
+
+impl IntegratorKind {
+    pub fn create(&self) -> Arc<dyn Integrator> {
+        match self {
+            IntegratorKind::Path => Arc::new(PathIntegrator::new()),
+            IntegratorKind::Whitted => Arc::new(WhittedIntegrator),
+            IntegratorKind::AmbientOcclusion => Arc::new(AmbientOcclusion::new(1_f64)),
+            IntegratorKind::Normals => Arc::new(DebugIntegrator::new(DebugView::Normals)),
+            IntegratorKind::Depth => Arc::new(DebugIntegrator::new(DebugView::Depth)),
+            IntegratorKind::Uv => Arc::new(DebugIntegrator::new(DebugView::Uv)),
+            IntegratorKind::MaterialId => Arc::new(DebugIntegrator::new(DebugView::MaterialId)),
+        }
+    }
+}
+
+impl FromStr for IntegratorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(IntegratorKind::Path),
+            "whitted" => Ok(IntegratorKind::Whitted),
+            "ao" | "ambient-occlusion" => Ok(IntegratorKind::AmbientOcclusion),
+            "normals" | "normal" => Ok(IntegratorKind::Normals),
+            "depth" => Ok(IntegratorKind::Depth),
+            "uv" => Ok(IntegratorKind::Uv),
+            "material" | "material-id" => Ok(IntegratorKind::MaterialId),
+            _ => Err(format!("unknown integrator '{}', expected path, whitted, ao, normals, depth, uv or material", s)),
+        }
+    }
+}
+
+impl fmt::Display for IntegratorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            IntegratorKind::Path => "path",
+            IntegratorKind::Whitted => "whitted",
+            IntegratorKind::AmbientOcclusion => "ao",
+            IntegratorKind::Normals => "normals",
+            IntegratorKind::Depth => "depth",
+            IntegratorKind::Uv => "uv",
+            IntegratorKind::MaterialId => "material",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+//unidirectional path tracer with next event estimation and multiple importance sampling.
+//past `min_bounces` paths are ended at random with a chance that grows as their throughput
+//drops, and the survivors are weighted up to match, so the result stays unbiased. max_depth
+//only stops paths that somehow survive that long
+pub struct PathIntegrator {
+    pub min_bounces: i32,
+}
+
+impl PathIntegrator {
+    pub fn new() -> Self {
+        Self {
+            min_bounces: 3,
+        }
+    }
+}
+
+impl Default for PathIntegrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Integrator for PathIntegrator {
+    fn li(&self, mut ray: Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color {
+        let mut radiance = Color::new_empty();
+        let mut throughput = Color::new(1_f64, 1_f64, 1_f64);
+        //density the last bounce sampled `ray` with, none for camera rays and specular bounces
+        //which light sampling can't reproduce
+        let mut bsdf_pdf: Option<f64> = None;
+
+        for bounce in 0..scene.max_depth.max(0) as u32 {
+            let mut hit_rec : HitRecord = HitRecord::new_empty();
+            if !scene.world.hit(&ray, Interval::new(0.001, INFINITY), &mut hit_rec) {
+                radiance += throughput * scene.escaped(&ray, bounce);
+                break;
+            }
+
+            let mat = hit_rec.mat.clone().unwrap();
+            let mut emitted = mat.emitted(&hit_rec);
+            //the previous bounce also sampled this light directly, so only count the bsdf sample's share
+            if let Some(bsdf_pdf) = bsdf_pdf
+                && mat.is_emissive() && !scene.lights.is_empty() {
+                let light_pdf = scene.lights.pdf_value(&ray.origin(), &ray.dir());
+                emitted = power_heuristic(bsdf_pdf, light_pdf)*emitted;
+            }
+            radiance += throughput * emitted;
+
+            sampler.set_dimension(bounce_dimension(bounce));
+            let Some(srec) = mat.scatter(&ray, &hit_rec, sampler) else {
+                break;
+            };
+            if !srec.specular {
+                radiance += throughput * sample_light(&ray, &hit_rec, mat.as_ref(), scene, bounce, true, sampler);
+            }
+            throughput = throughput * srec.weight(&hit_rec.normal);
+            bsdf_pdf = (!srec.specular).then_some(srec.pdf);
+            ray = Ray::new(hit_rec.p, srec.direction);
+
+            let max_throughput = throughput.x().max(throughput.y()).max(throughput.z());
+            if max_throughput <= 0_f64 {
+                break;
+            }
+            if bounce as i32 + 1 >= self.min_bounces && max_throughput < 1_f64 {
+                let survive = max_throughput.max(0.05);
+                sampler.set_dimension(bounce_dimension(bounce) + ROULETTE_DIMENSION_OFFSET);
+                if sampler.get_1d() >= survive {
+                    break;
+                }
+                throughput /= survive;
+            }
+        }
+        radiance
+    }
+}
+
+//whitted style ray tracing: direct light only on diffuse surfaces, with mirror and glass bounces
+//followed up to max_depth. no indirect diffuse light, so it's fast but misses color bleeding
+//and only sees lights through shadow rays
+pub struct WhittedIntegrator;
+
+impl Integrator for WhittedIntegrator {
+    fn li(&self, mut ray: Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color {
+        let mut radiance = Color::new_empty();
+        let mut throughput = Color::new(1_f64, 1_f64, 1_f64);
+        for bounce in 0..scene.max_depth.max(0) as u32 {
+            let mut hit_rec : HitRecord = HitRecord::new_empty();
+            if !scene.world.hit(&ray, Interval::new(0.001, INFINITY), &mut hit_rec) {
+                radiance += throughput * scene.escaped(&ray, bounce);
+                break;
+            }
+
+            let mat = hit_rec.mat.clone().unwrap();
+            radiance += throughput * mat.emitted(&hit_rec);
+            sampler.set_dimension(bounce_dimension(bounce));
+            let Some(srec) = mat.scatter(&ray, &hit_rec, sampler) else {
+                break;
+            };
+            if !srec.specular {
+                radiance += throughput * sample_light(&ray, &hit_rec, mat.as_ref(), scene, bounce, false, sampler);
+                break;
+            }
+            throughput = throughput * srec.weight(&hit_rec.normal);
+            ray = Ray::new(hit_rec.p, srec.direction);
+        }
+        radiance
+    }
+}
+
+//fraction of the cosine weighted hemisphere above the first hit that's open out to `distance`,
+//white where camera rays miss everything
+pub struct AmbientOcclusion {
+    pub distance: f64,
+}
+
+impl AmbientOcclusion {
+    pub fn new(distance: f64) -> Self {
+        Self {
+            distance,
+        }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn li(&self, ray: Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Color {
+        let mut hit_rec : HitRecord = HitRecord::new_empty();
+        if !scene.world.hit(&ray, Interval::new(0.001, INFINITY), &mut hit_rec) {
+            return match scene.transparent_background {
+                true => Color::new_empty(),
+                false => Color::new(1_f64, 1_f64, 1_f64),
+            };
+        }
+        sampler.set_dimension(bounce_dimension(0));
+        let direction = Onb::new(&hit_rec.normal).transform(&random_cosine_direction(sampler.get_2d()));
+        let mut occluder = HitRecord::new_empty();
+        match scene.world.hit(&Ray::new(hit_rec.p, direction), Interval::new(0.001, self.distance), &mut occluder) {
+            true => Color::new_empty(),
+            false => Color::new(1_f64, 1_f64, 1_f64),
+        }
+    }
+}
+
+//what DebugIntegrator shows about the first hit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugView {
+    Normals, //outward surface normal mapped from [-1, 1] to [0, 1]
+    Depth, //distance from the camera as 1/(1 + d), white up close and fading out with distance
+    Uv, //texture coordinates in red and green
+    MaterialId, //a flat color per material
+}
+
+//shows one property of whatever camera rays hit first, black where they miss
+pub struct DebugIntegrator {
+    pub view: DebugView,
+}
+
+impl DebugIntegrator {
+    pub fn new(view: DebugView) -> Self {
+        Self {
+            view,
+        }
+    }
+}
+
+impl Integrator for DebugIntegrator {
+    fn li(&self, ray: Ray, scene: &Scene, _: &mut dyn Sampler) -> Color {
+        let mut hit_rec : HitRecord = HitRecord::new_empty();
+        if !scene.world.hit(&ray, Interval::new(0.001, INFINITY), &mut hit_rec) {
+            return Color::new_empty();
+        }
+        match self.view {
+            DebugView::Normals => {
+                let n = if hit_rec.front_face { hit_rec.normal } else { -hit_rec.normal };
+                0.5*(n + Color::new(1_f64, 1_f64, 1_f64))
+            }
+            DebugView::Depth => {
+                let d = 1_f64/(1_f64 + hit_rec.t*ray.dir().get_len());
+                Color::new(d, d, d)
+            }
+            DebugView::Uv => Color::new(hit_rec.u, hit_rec.v, 0_f64),
+            DebugView::MaterialId => hit_rec.mat.as_ref().map_or(Color::new_empty(), |mat| id_color(mat.id() as u64)),
+        }
+    }
+}
+
+//next event estimation: radiance from a direction picked by sampling the lights. with `mis`, area
+//lights are weighted against the bsdf sampling of the same direction with the power heuristic,
+//without it the caller must not pick up emission through bsdf samples. punctual lights can't be
+//hit by bsdf samples so they always get the whole contribution
+pub fn sample_light(ray: &Ray, hit_rec: &HitRecord, mat: &dyn Scatter, scene: &Scene, bounce: u32, mis: bool, sampler: &mut dyn Sampler) -> Color {
+    sampler.set_dimension(bounce_dimension(bounce) + LIGHT_DIMENSION_OFFSET);
+    let u = sampler.get_2d();
+    let u_select = sampler.get_1d();
+    match scene.lights.sample(&hit_rec.p, u_select, u) {
+        Some(LightSample::Area(direction)) => {
+            let light_pdf = scene.lights.pdf_value(&hit_rec.p, &direction);
+            if light_pdf <= 0_f64 {
+                return Color::new_empty();
+            }
+            let bsdf = mat.bsdf(ray, hit_rec, &direction);
+            if bsdf.near_zero() {
+                return Color::new_empty();
+            }
+
+            //whatever the shadow ray hits first is what's seen in that direction, an occluder just emits nothing
+            let mut light_rec = HitRecord::new_empty();
+            if !scene.world.hit(&Ray::new(hit_rec.p, direction), Interval::new(0.001, INFINITY), &mut light_rec) {
+                return Color::new_empty();
+            }
+            let emitted = light_rec.mat.as_ref().map_or(Color::new_empty(), |light_mat| light_mat.emitted(&light_rec));
+            let cos_theta = dot(&unit_vector(&direction), &hit_rec.normal).abs();
+            let weight = match mis {
+                true => power_heuristic(light_pdf, mat.pdf(ray, hit_rec, &direction)),
+                false => 1_f64,
+            };
+            (weight*cos_theta/light_pdf) * bsdf * emitted
+        }
+        Some(LightSample::Punctual(sample)) => {
+            let bsdf = mat.bsdf(ray, hit_rec, &sample.direction);
+            if bsdf.near_zero() || sample.radiance.near_zero() {
+                return Color::new_empty();
+            }
+            let mut shadow_rec = HitRecord::new_empty();
+            if scene.world.hit(&Ray::new(hit_rec.p, sample.direction), Interval::new(0.001, sample.distance - 0.001), &mut shadow_rec) {
+                return Color::new_empty();
+            }
+            let cos_theta = dot(&sample.direction, &hit_rec.normal).abs();
+            (cos_theta/scene.lights.selection_pdf()) * bsdf * sample.radiance
+        }
+        None => Color::new_empty(),
+    }
+}
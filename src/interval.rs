@@ -1,9 +1,13 @@
+#[derive(Clone, Copy, Debug)]
 pub struct Interval {
     pub min : f64,
     pub max : f64,
 }
 
 impl Interval {
+    pub const EMPTY: Interval = Interval { min: f64::INFINITY, max: f64::NEG_INFINITY };
+    pub const UNIVERSE: Interval = Interval { min: f64::NEG_INFINITY, max: f64::INFINITY };
+
     pub fn new(min: f64, max: f64) -> Self {
         Self {
             min,
@@ -11,6 +15,22 @@ impl Interval {
         }
     }
 
+    //tightest interval containing both a and b
+    pub fn enclosing(a: &Interval, b: &Interval) -> Self {
+        Self {
+            min: a.min.min(b.min),
+            max: a.max.max(b.max),
+        }
+    }
+
+    pub fn size(&self) -> f64 {
+        self.max - self.min
+    }
+
+    pub fn contains(&self, x: f64) -> bool {
+        self.min <= x && x <= self.max
+    }
+
     pub fn surrounds(&self, x: f64) -> bool {
         self.min < x && x < self.max
     }
@@ -22,4 +42,9 @@ impl Interval {
             _ => x,
         }
     }
+
+    pub fn expand(&self, delta: f64) -> Self {
+        let padding = delta/2_f64;
+        Self::new(self.min - padding, self.max + padding)
+    }
 }
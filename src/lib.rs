@@ -0,0 +1,30 @@
+pub mod vec3;
+pub mod color;
+pub mod colorspace;
+pub mod ray;
+pub mod sphere;
+pub mod helper;
+pub mod noise;
+pub mod interval;
+pub mod camera;
+pub mod material;
+pub mod image;
+pub mod output;
+pub mod tonemap;
+pub mod exr;
+pub mod aabb;
+pub mod bvh;
+pub mod triangle;
+pub mod obj;
+pub mod input;
+pub mod background;
+pub mod texture;
+pub mod sampler;
+pub mod accumulator;
+pub mod progress;
+pub mod onb;
+pub mod light;
+pub mod integrator;
+pub mod aov;
+#[cfg(test)]
+mod test_util;
@@ -0,0 +1,212 @@
+use std::sync::Arc;
+
+use crate::{color::Color, helper::{deg_to_rad, INFINITY, PI}, onb::Onb, ray::Hittable, vec3::{dot, unit_vector, Point3, Vec3}};
+
+//light with no surface of its own, so it's only ever reached through shadow rays and stays
+//invisible to camera and bsdf rays
+pub trait PunctualLight: Send + Sync {
+    //light arriving at `p`, `u` places the sample on lights with some extent
+    fn sample(&self, p: &Point3, u: [f64; 2]) -> PunctualSample;
+}
+
+//unit direction from the shaded point to the light, how far away the light is (infinite for
+//directional lights) and the radiance arriving along it, already divided by the pdf of the direction
+#[derive(Clone, Copy, Debug)]
+pub struct PunctualSample {
+    pub direction: Vec3,
+    pub distance: f64,
+    pub radiance: Color,
+}
+
+//emits `intensity` (radiant intensity, falling off with the square of the distance) in every direction
+pub struct PointLight {
+    position: Point3,
+    intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl PunctualLight for PointLight {
+    fn sample(&self, p: &Point3, _: [f64; 2]) -> PunctualSample {
+        let to_light = self.position - *p;
+        let dist_squared = to_light.get_len_squared();
+        PunctualSample {
+            direction: unit_vector(&to_light),
+            distance: dist_squared.sqrt(),
+            radiance: self.intensity/dist_squared,
+        }
+    }
+}
+
+//point light restricted to a cone around `direction`, at full intensity out to `falloff_start`
+//degrees off axis and smoothly fading to nothing at `cone_angle` degrees
+pub struct SpotLight {
+    position: Point3,
+    direction: Vec3,
+    intensity: Color,
+    cos_cone: f64,
+    cos_falloff_start: f64,
+}
+
+impl SpotLight {
+    pub fn new(position: Point3, direction: Vec3, intensity: Color, cone_angle: f64, falloff_start: f64) -> Self {
+        let cone_angle = cone_angle.clamp(0_f64, 180_f64);
+        Self {
+            position,
+            direction: unit_vector(&direction),
+            intensity,
+            cos_cone: deg_to_rad(cone_angle).cos(),
+            cos_falloff_start: deg_to_rad(falloff_start.clamp(0_f64, cone_angle)).cos(),
+        }
+    }
+
+    pub fn looking_at(position: Point3, target: Point3, intensity: Color, cone_angle: f64, falloff_start: f64) -> Self {
+        Self::new(position, target - position, intensity, cone_angle, falloff_start)
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta >= self.cos_falloff_start {
+            return 1_f64;
+        }
+        if cos_theta <= self.cos_cone {
+            return 0_f64;
+        }
+        let t = (cos_theta - self.cos_cone)/(self.cos_falloff_start - self.cos_cone);
+        t*t*(3_f64 - 2_f64*t)
+    }
+}
+
+impl PunctualLight for SpotLight {
+    fn sample(&self, p: &Point3, _: [f64; 2]) -> PunctualSample {
+        let to_light = self.position - *p;
+        let dist_squared = to_light.get_len_squared();
+        let direction = unit_vector(&to_light);
+        let falloff = self.falloff(dot(&(-direction), &self.direction));
+        PunctualSample {
+            direction,
+            distance: dist_squared.sqrt(),
+            radiance: (falloff/dist_squared)*self.intensity,
+        }
+    }
+}
+
+//infinitely far away light like the sun, shining along `direction` with `irradiance` on a surface
+//facing it. a nonzero `angular_diameter` (degrees, about 0.53 for the real sun) spreads the
+//light over a disk of directions for soft shadows without changing the total irradiance
+pub struct DirectionalLight {
+    direction: Vec3,
+    irradiance: Color,
+    pub angular_diameter: f64,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Color) -> Self {
+        Self {
+            direction: unit_vector(&direction),
+            irradiance,
+            angular_diameter: 0_f64,
+        }
+    }
+}
+
+impl PunctualLight for DirectionalLight {
+    fn sample(&self, _: &Point3, u: [f64; 2]) -> PunctualSample {
+        let to_light = -self.direction;
+        let direction = match self.angular_diameter {
+            d if d <= 0_f64 => to_light,
+            d => {
+                //uniform over the cone, so radiance over pdf is just the irradiance
+                let cos_max = deg_to_rad(d.min(180_f64)/2_f64).cos();
+                let z = 1_f64 - u[0]*(1_f64 - cos_max);
+                let r = (1_f64 - z*z).max(0_f64).sqrt();
+                let phi = 2_f64*PI*u[1];
+                Onb::new(&to_light).transform(&Vec3::new(r*phi.cos(), r*phi.sin(), z))
+            }
+        };
+        PunctualSample {
+            direction,
+            distance: INFINITY,
+            radiance: self.irradiance,
+        }
+    }
+}
+
+//what LightList::sample picked
+pub enum LightSample {
+    //a direction towards an area light, what's actually there is found by tracing it
+    Area(Vec3),
+    Punctual(PunctualSample),
+}
+
+//every light of a scene, sampled directly for next event estimation: the emissive shapes and
+//the punctual lights
+pub struct LightList {
+    lights: Vec<Arc<dyn Hittable>>,
+    punctual: Vec<Arc<dyn PunctualLight>>,
+}
+
+impl LightList {
+    pub fn new(lights: Vec<Arc<dyn Hittable>>, punctual: Vec<Arc<dyn PunctualLight>>) -> Self {
+        Self {
+            lights,
+            punctual,
+        }
+    }
+
+    //every primitive in `world` with an emissive material, plus the given punctual lights
+    pub fn from_world(world: &dyn Hittable, punctual: &[Arc<dyn PunctualLight>]) -> Self {
+        Self::new(world.lights(), punctual.to_vec())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len() + self.punctual.len()
+    }
+
+    //probability of picking any one light
+    pub fn selection_pdf(&self) -> f64 {
+        1_f64/self.len() as f64
+    }
+
+    //picks a light uniformly with `u_select`, then a sample on it with `u`
+    pub fn sample(&self, origin: &Point3, u_select: f64, u: [f64; 2]) -> Option<LightSample> {
+        if self.is_empty() {
+            return None;
+        }
+        let idx = ((u_select*self.len() as f64) as usize).min(self.len() - 1);
+        match self.lights.get(idx) {
+            Some(light) => Some(LightSample::Area(light.sample_direction(origin, u))),
+            None => Some(LightSample::Punctual(self.punctual[idx - self.lights.len()].sample(origin, u))),
+        }
+    }
+
+    //density of `sample` picking `direction` through an area light. a direction can line up
+    //with more than one light, so this sums over all of them
+    pub fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        if self.lights.is_empty() {
+            return 0_f64;
+        }
+        let sum: f64 = self.lights.iter().map(|light| light.pdf_value(origin, direction)).sum();
+        sum*self.selection_pdf()
+    }
+}
+
+//veach's power heuristic (beta = 2) weight for a sample drawn from the strategy with density
+//`pdf` when `other_pdf` could also have produced it
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf*pdf, other_pdf*other_pdf);
+    match a + b {
+        sum if sum > 0_f64 => a/sum,
+        _ => 0_f64,
+    }
+}
@@ -1,72 +1,439 @@
-mod vec3;
-mod color;
-mod ray;
-mod sphere;
-mod helper;
-mod interval;
-mod camera;
-mod material;
-
-use camera::Camera;
-use color::Color;
-use helper::{random_f64, random_f64_range};
-use material::Dielectric;
-use material::{Lambertian, Metal};
-use sphere::Sphere;
-use vec3::Vec3;
-use vec3::Point3;
-use std::rc::Rc;
-use ray::{HittableList, Scatter};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use raytracer_rs::accumulator::Accumulator;
+use raytracer_rs::aov::Aov;
+
+use raytracer_rs::bvh::Bvh;
+use raytracer_rs::background::GradientBackground;
+use raytracer_rs::camera::{AdaptiveSampling, Camera, ProgressiveSettings};
+use raytracer_rs::color::Color;
+use raytracer_rs::colorspace::ColorSpace;
+use raytracer_rs::image::Image;
+use raytracer_rs::helper::{random_f64, random_f64_range, Rng};
+use raytracer_rs::integrator::IntegratorKind;
+use raytracer_rs::material::Dielectric;
+use raytracer_rs::material::{Lambertian, Metal};
+use raytracer_rs::noise::{MarbleTexture, Perlin};
+use raytracer_rs::exr::ExrCompression;
+use raytracer_rs::output::{unpremultiply, write_image, write_ppm, BitDepth, ImageFormat, OutputSettings};
+use raytracer_rs::sphere::Sphere;
+use raytracer_rs::vec3::Vec3;
+use raytracer_rs::vec3::Point3;
+use raytracer_rs::progress::{ProgressObserver, RenderProgress, TileRect};
+use raytracer_rs::ray::{HittableList, Scatter};
+use raytracer_rs::sampler::SamplerKind;
+use raytracer_rs::tonemap::DisplayTransform;
+
+//seed used for the scene layout and the render unless --seed is passed
+const DEFAULT_SEED: u64 = 0;
+//samples added per pixel between checkpoints and time budget checks in progressive mode
+const SAMPLES_PER_PASS: u32 = 4;
+
+//width in characters of the bar itself
+const PROGRESS_BAR_WIDTH: usize = 30;
+//minimum time between redraws so hundreds of small tiles don't flood the terminal
+const PROGRESS_REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+//progress bar on stderr, redrawn in place
+struct ProgressBar {
+    last_draw: Mutex<Option<Instant>>,
+}
+
+impl ProgressBar {
+    fn new() -> Self {
+        Self { last_draw: Mutex::new(None) }
+    }
+
+    fn draw(&self, progress: &RenderProgress) {
+        let fraction = progress.fraction();
+        let filled = ((fraction*PROGRESS_BAR_WIDTH as f64) as usize).min(PROGRESS_BAR_WIDTH);
+        let mut line = format!("\r[{}{}] {:5.1}%", "#".repeat(filled), "-".repeat(PROGRESS_BAR_WIDTH - filled), 100_f64*fraction);
+        match progress.passes_total {
+            Some(1) => {}
+            Some(passes) => line += &format!("  pass {}/{}", (progress.pass + 1).min(passes), passes),
+            None => line += &format!("  pass {}", progress.pass + 1),
+        }
+        line += &format!("  {} elapsed", format_duration(progress.elapsed));
+        if let Some(eta) = progress.eta() {
+            line += &format!(", {} left", format_duration(eta));
+        }
+        //pad so a shorter line fully covers the previous one
+        eprint!("{:<72}", line);
+        io::stderr().flush().unwrap();
+    }
+}
+
+impl ProgressObserver for ProgressBar {
+    fn tile_finished(&self, _tile: &TileRect, progress: &RenderProgress) {
+        let mut last_draw = self.last_draw.lock().unwrap();
+        if last_draw.is_some_and(|t| t.elapsed() < PROGRESS_REDRAW_INTERVAL) {
+            return;
+        }
+        *last_draw = Some(Instant::now());
+        self.draw(progress);
+    }
+
+    fn render_finished(&self, progress: &RenderProgress, cancelled: bool) {
+        let _guard = self.last_draw.lock().unwrap();
+        self.draw(progress);
+        match cancelled {
+            true => eprintln!("\nCancelled, writing partial image"),
+            false => eprintln!("\nDone"),
+        }
+    }
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        s if s >= 3600 => format!("{}:{:02}:{:02}", s/3600, s/60 % 60, s % 60),
+        s => format!("{}:{:02}", s/60, s % 60),
+    }
+}
 
 fn main() {
+    let mut seed = DEFAULT_SEED;
+    let mut sampler = SamplerKind::Independent;
+    let mut integrator = IntegratorKind::Path;
+    let mut samples_per_pixel = 10;
+    let mut noise_threshold = None;
+    let mut checkpoint: Option<PathBuf> = None;
+    let mut time_limit = None;
+    let mut aovs: Vec<Aov> = Vec::new();
+    let mut aov_prefix = String::from("aov");
+    let mut output: Option<PathBuf> = None;
+    let mut output_settings = OutputSettings::default();
+    let mut alpha = false;
+    let mut display = DisplayTransform::new();
+    let mut working_space = ColorSpace::LinearSrgb;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => {
+                seed = args.next().and_then(|s| s.parse().ok()).unwrap_or_else(|| {
+                    eprintln!("--seed expects a non-negative integer");
+                    std::process::exit(2);
+                });
+            }
+            "--spp" => {
+                samples_per_pixel = args.next().and_then(|s| s.parse().ok()).filter(|&n| n > 0).unwrap_or_else(|| {
+                    eprintln!("--spp expects a positive integer");
+                    std::process::exit(2);
+                });
+            }
+            "--noise-threshold" => {
+                noise_threshold = Some(args.next().and_then(|s| s.parse().ok()).filter(|&t: &f64| t > 0_f64).unwrap_or_else(|| {
+                    eprintln!("--noise-threshold expects a positive number");
+                    std::process::exit(2);
+                }));
+            }
+            "--checkpoint" => {
+                checkpoint = Some(args.next().map(PathBuf::from).unwrap_or_else(|| {
+                    eprintln!("--checkpoint expects a file path");
+                    std::process::exit(2);
+                }));
+            }
+            "--time-limit" => {
+                time_limit = Some(args.next().and_then(|s| s.parse().ok()).filter(|&t: &f64| t > 0_f64).map(Duration::from_secs_f64).unwrap_or_else(|| {
+                    eprintln!("--time-limit expects a positive number of seconds");
+                    std::process::exit(2);
+                }));
+            }
+            "--integrator" => {
+                integrator = args.next().unwrap_or_default().parse().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(2);
+                });
+            }
+            "-o" | "--output" => {
+                let path = args.next().map(PathBuf::from).unwrap_or_else(|| {
+                    eprintln!("{} expects a file path", arg);
+                    std::process::exit(2);
+                });
+                if ImageFormat::from_path(&path).is_none() {
+                    eprintln!("can't tell the format of '{}', expected a .ppm, .png, .pfm or .exr extension", path.display());
+                    std::process::exit(2);
+                }
+                output = Some(path);
+            }
+            "--bit-depth" => {
+                output_settings.bit_depth = match args.next().as_deref() {
+                    Some("8") => Some(BitDepth::Eight),
+                    Some("16") => Some(BitDepth::Sixteen),
+                    Some("32") => Some(BitDepth::ThirtyTwo),
+                    _ => {
+                        eprintln!("--bit-depth expects 8, 16 or 32");
+                        std::process::exit(2);
+                    }
+                };
+            }
+            "--compression" => {
+                output_settings.compression = match args.next().as_deref() {
+                    Some("none") => ExrCompression::None,
+                    Some("zip") => ExrCompression::Zip,
+                    _ => {
+                        eprintln!("--compression expects none or zip");
+                        std::process::exit(2);
+                    }
+                };
+            }
+            "--alpha" => alpha = true,
+            "--working-space" => {
+                working_space = args.next().unwrap_or_default().parse().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(2);
+                });
+            }
+            "--output-space" => {
+                output_settings.color_space = args.next().unwrap_or_default().parse().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(2);
+                });
+            }
+            "--exposure" => {
+                display.exposure = args.next().and_then(|s| s.parse().ok()).filter(|ev: &f64| ev.is_finite()).unwrap_or_else(|| {
+                    eprintln!("--exposure expects a number of stops");
+                    std::process::exit(2);
+                });
+            }
+            "--tonemap" => {
+                display.tone_map = args.next().unwrap_or_default().parse().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(2);
+                });
+            }
+            "--white-point" => {
+                display.white_point = args.next().and_then(|s| s.parse().ok()).filter(|&w: &f64| w > 0_f64).unwrap_or_else(|| {
+                    eprintln!("--white-point expects a positive luminance");
+                    std::process::exit(2);
+                });
+            }
+            "--aovs" => {
+                for name in args.next().unwrap_or_default().split(',').filter(|s| !s.is_empty()) {
+                    aovs.push(name.parse().unwrap_or_else(|e| {
+                        eprintln!("{}", e);
+                        std::process::exit(2);
+                    }));
+                }
+            }
+            "--aov-prefix" => {
+                aov_prefix = args.next().unwrap_or_else(|| {
+                    eprintln!("--aov-prefix expects a path prefix");
+                    std::process::exit(2);
+                });
+            }
+            "--sampler" => {
+                sampler = args.next().unwrap_or_default().parse().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    std::process::exit(2);
+                });
+            }
+            _ => {
+                eprintln!("unknown argument '{}'", arg);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let progressive = checkpoint.is_some() || time_limit.is_some();
+    if progressive && noise_threshold.is_some() {
+        eprintln!("--noise-threshold can't be combined with --checkpoint or --time-limit");
+        std::process::exit(2);
+    }
+
+    //resuming has to rebuild the exact scene and sample sequences, so the saved seed wins
+    let resumed = match &checkpoint {
+        Some(path) if path.exists() => {
+            let acc = Accumulator::load(path).unwrap_or_else(|e| {
+                eprintln!("failed to load checkpoint '{}': {}", path.display(), e);
+                std::process::exit(1);
+            });
+            eprintln!("Resuming from {} samples per pixel", acc.min_samples());
+            seed = acc.seed();
+            Some(acc)
+        }
+        _ => None,
+    };
+
+    let mut rng = Rng::new(seed);
     let mut world : HittableList = HittableList::new();
+    //the scene's colors are picked in srgb and rendered in the working space
+    let to_working = ColorSpace::LinearSrgb.conversion(working_space);
 
-    let material_ground = Rc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
-    world.add(Rc::new(Sphere::new(Point3::new(0.0, -1000_f64, 0.0), 1000.0, material_ground)));
+    let ground_texture = Arc::new(MarbleTexture::new(Arc::new(Perlin::new(rng.next_u64())), 4.0, to_working.transform(&Color::new(0.6, 0.6, 0.6)), to_working.transform(&Color::new(0.3, 0.3, 0.35))));
+    let material_ground = Arc::new(Lambertian::from_texture(ground_texture));
+    world.add(Arc::new(Sphere::new(Point3::new(0.0, -1000_f64, 0.0), 1000.0, material_ground)));
 
     for a in -11..11 {
         for b in -11..11 {
-            let choose_mat = random_f64();
-            let center = Point3::new(a as f64 + 0.9*random_f64(), 0.2, b as f64 + 0.9*random_f64()); 
+            let choose_mat = random_f64(&mut rng);
+            let center = Point3::new(a as f64 + 0.9*random_f64(&mut rng), 0.2, b as f64 + 0.9*random_f64(&mut rng)); 
             if (center - Point3::new(4_f64, 0.2_f64, 0_f64)).get_len() > 0.9 {
-                let sphere_material : Rc<dyn Scatter>; 
+                let sphere_material : Arc<dyn Scatter>; 
                 match choose_mat {
                     x if x < 0.8 => {
-                        let albedo = Color::random() * Color::random();
-                        sphere_material = Rc::new(Lambertian::new(albedo));
-                        world.add(Rc::new(Sphere::new(center, 0.2, sphere_material)));
+                        let albedo = Color::random(&mut rng) * Color::random(&mut rng);
+                        sphere_material = Arc::new(Lambertian::new(to_working.transform(&albedo)));
+                        world.add(Arc::new(Sphere::new(center, 0.2, sphere_material)));
                     }
                     x if x < 0.85 => {
-                        let albedo = Color::random_range(0.5, 1_f64);
-                        let fuzz = random_f64_range(0_f64, 0.5);
-                        sphere_material = Rc::new(Metal::new(albedo, fuzz));
-                        world.add(Rc::new(Sphere::new(center, 0.2, sphere_material)));
+                        let albedo = Color::random_range(&mut rng, 0.5, 1_f64);
+                        let fuzz = random_f64_range(&mut rng, 0_f64, 0.5);
+                        sphere_material = Arc::new(Metal::new(to_working.transform(&albedo), fuzz));
+                        world.add(Arc::new(Sphere::new(center, 0.2, sphere_material)));
                     }
                     _ => {
-                        sphere_material = Rc::new(Dielectric::new(1.5));
-                        world.add(Rc::new(Sphere::new(center, 0.2, sphere_material)));
+                        sphere_material = Arc::new(Dielectric::new(1.5));
+                        world.add(Arc::new(Sphere::new(center, 0.2, sphere_material)));
                     }
                 }
             }
         }
     }
 
-    let material1 = Rc::new(Dielectric::new(1.5));
-    world.add(Rc::new(Sphere::new(Point3::new(0_f64, 1_f64, 0_f64), 1.0, material1)));
+    let material1 = Arc::new(Dielectric::new(1.5));
+    world.add(Arc::new(Sphere::new(Point3::new(0_f64, 1_f64, 0_f64), 1.0, material1)));
+
+    let material2 = Arc::new(Lambertian::new(to_working.transform(&Color::new(0.4, 0.2, 0.1))));
+    world.add(Arc::new(Sphere::new(Point3::new(-4_f64, 1_f64, 0_f64), 1.0, material2)));
 
-    let material2 = Rc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
-    world.add(Rc::new(Sphere::new(Point3::new(-4_f64, 1_f64, 0_f64), 1.0, material2)));
+    let material3 = Arc::new(Metal::new(to_working.transform(&Color::new(0.7, 0.6, 0.5)), 0.0));
+    world.add(Arc::new(Sphere::new(Point3::new(4_f64, 1_f64, 0_f64), 1.0, material3)));
 
-    let material3 = Rc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
-    world.add(Rc::new(Sphere::new(Point3::new(4_f64, 1_f64, 0_f64), 1.0, material3)));
+    let format = output.as_ref().and_then(ImageFormat::from_path).unwrap_or(ImageFormat::Ppm);
 
     //aspect ratio, img_width, samples_per_pixel, depth, vertical angle fov
-    let mut cam : Camera = Camera::new(16_f64/9_f64, 1200, 10, 50, 20_f64);
+    let mut cam : Camera = Camera::new(16_f64/9_f64, 1200, samples_per_pixel, 50, 20_f64);
 
     cam.lookfrom = Point3::new(13.0,2.0,3.0);
     cam.lookat   = Point3::new(0.0,0.0,0.0);
     cam.vup      = Vec3::new(0.0,1.0,0.0);
     cam.defocus_angle = 0.6;
     cam.focus_dist = 10.0;
-    cam.render(&world);
+    cam.background = Arc::new(GradientBackground::sky_in(working_space));
+    cam.working_space = working_space;
+    //alpha channels hold premultiplied colors, so the background mustn't be in them
+    cam.transparent_background = alpha && format.has_alpha();
+    cam.seed = rng.next_u64();
+    cam.sampler = sampler;
+    cam.integrator = integrator.create();
+    //with a threshold, --spp becomes the per-pixel budget instead of a fixed count
+    cam.adaptive = noise_threshold.map(|noise_threshold| AdaptiveSampling {
+        min_samples: 8.min(samples_per_pixel),
+        max_samples: samples_per_pixel,
+        noise_threshold,
+    });
+    cam.progress = Arc::new(ProgressBar::new());
+    //first ctrl-c finishes the tiles in flight and writes what's there, a second one quits outright
+    let cancel = cam.cancel.clone();
+    ctrlc::set_handler(move || {
+        if cancel.is_cancelled() {
+            std::process::exit(130);
+        }
+        cancel.cancel();
+    }).expect("failed to install ctrl-c handler");
+
+    let world = Bvh::new(world);
+    let (width, height) = cam.image_size();
+    if let Some(acc) = &resumed {
+        if (acc.width(), acc.height()) != (width, height) {
+            eprintln!("checkpoint is for a {}x{} image but this render is {}x{}", acc.width(), acc.height(), width, height);
+            std::process::exit(1);
+        }
+        if acc.sampler() != sampler {
+            eprintln!("checkpoint was rendered with --sampler {} but this render uses {}", acc.sampler(), sampler);
+            std::process::exit(1);
+        }
+    }
+
+    //the aovs go first, so the coverage that goes with a transparent background is there even
+    //when the image itself gets cancelled
+    let mut layer_aovs = aovs.clone();
+    if alpha && !layer_aovs.contains(&Aov::Coverage) {
+        layer_aovs.push(Aov::Coverage);
+    }
+    let mut layers = match layer_aovs.is_empty() {
+        true => Vec::new(),
+        false => cam.render_aovs(&world, &layer_aovs),
+    };
+
+    let mut image = if progressive {
+        let mut acc = resumed.unwrap_or_else(|| Accumulator::new(width, height, seed, sampler));
+        let settings = ProgressiveSettings {
+            samples_per_pass: SAMPLES_PER_PASS,
+            target_samples: Some(samples_per_pixel as u32),
+            time_budget: time_limit,
+        };
+        cam.render_progressive(&world, &mut acc, &settings, |acc| {
+            if let Some(path) = &checkpoint
+                && let Err(e) = acc.save(path) {
+                eprintln!("failed to save checkpoint '{}': {}", path.display(), e);
+            }
+        });
+        acc.image()
+    } else {
+        cam.render(&world)
+    };
+
+    //albedo is the only aov holding colors
+    for (aov, layer) in layer_aovs.iter().zip(&mut layers) {
+        if *aov == Aov::Albedo {
+            working_space.convert_image(layer, output_settings.color_space);
+        }
+    }
+    let coverage = layer_aovs.iter().position(|&aov| aov == Aov::Coverage).and_then(|i| layers.get(i));
+
+    //hdr formats keep the radiance as rendered, only moved to the output primaries. everything
+    //else gets exposed and tone mapped first, in rec.709 since the operators were built for it
+    match format.is_hdr() {
+        true => working_space.convert_image(&mut image, output_settings.color_space),
+        false => {
+            //tone mapping a premultiplied color isn't the same as premultiplying the tone mapped
+            //one, so the display transform gets the straight colors png alpha wants anyway
+            if let Some(coverage) = coverage.filter(|_| cam.transparent_background) {
+                unpremultiply(&mut image, coverage);
+            }
+            working_space.convert_image(&mut image, ColorSpace::LinearSrgb);
+            image = display.apply_image(&image);
+            ColorSpace::LinearSrgb.convert_image(&mut image, output_settings.color_space);
+        }
+    }
+
+    //layered formats take the aovs along in the same file, raw
+    let embedded: Vec<(Aov, &Image)> = match format.has_layers() {
+        true => aovs.iter().copied().zip(&layers).collect(),
+        false => Vec::new(),
+    };
+    match &output {
+        Some(path) => {
+            if let Err(e) = write_image(path, &image, coverage.filter(|_| alpha), &embedded, &output_settings) {
+                eprintln!("failed to write image: {}", e);
+                std::process::exit(1);
+            }
+        }
+        None => {
+            let mut out = BufWriter::new(io::stdout().lock());
+            write_ppm(&image, false, &mut out).expect("failed to write image to stdout");
+        }
+    }
+    if format.has_layers() {
+        return;
+    }
+
+    //otherwise every aov gets a file of the image's format, mapped for viewing unless that's hdr
+    for (aov, mut layer) in aovs.iter().zip(layers) {
+        if !format.is_hdr() {
+            for pixel in layer.pixels_mut() {
+                *pixel = aov.display(pixel);
+            }
+        }
+        let path = format!("{}.{}.{}", aov_prefix, aov, format.extension());
+        let settings = OutputSettings { raw: !aov.is_color(), ..output_settings };
+        if let Err(e) = write_image(&path, &layer, None, &[], &settings) {
+            eprintln!("failed to write aov: {}", e);
+        }
+    }
 }
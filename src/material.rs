@@ -1,63 +1,113 @@
-use crate::{color::Color, helper::random_f64, ray::{HitRecord, Ray, Scatter}, vec3::{dot, random_unit_vector, reflect, refract, unit_vector}};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{color::Color, helper::PI, onb::Onb, texture::{SolidColor, Texture}, ray::{HitRecord, Ray, Scatter, ScatterRecord}, sampler::Sampler, vec3::{dot, random_cosine_direction, random_unit_vector, reflect, refract, unit_vector, Vec3}};
+
+//materials are numbered in the order they're created, so the same scene gets the same ids in
+//every run. 0 is left for the background
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+fn next_id() -> u32 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
 
 pub struct Lambertian {
-    albedo: Color
+    tex: Arc<dyn Texture>,
+    id: u32,
 }
 
 impl Lambertian {
     pub fn new(color : Color) -> Self  {
+        Self::from_texture(Arc::new(SolidColor::new(color)))
+    }
+
+    pub fn from_texture(tex: Arc<dyn Texture>) -> Self {
         Self {
-        albedo: color,
+        tex,
+        id : next_id(),
         }
     }
 }
 
+//samples the cosine weighted hemisphere, which is exactly proportional to bsdf*cos
 impl Scatter for Lambertian {
-    fn scatter(&self, _: &Ray, hit_rec: &HitRecord, attenuation: &mut Color, scattered_ray: &mut Ray) -> bool {
-        let mut scatter_direction = hit_rec.normal + random_unit_vector();
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn scatter(&self, ray_in: &Ray, hit_rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord> {
+        let direction = Onb::new(&hit_rec.normal).transform(&random_cosine_direction(sampler.get_2d()));
+        let pdf = self.pdf(ray_in, hit_rec, &direction);
+        Some(ScatterRecord::sampled(direction, self.bsdf(ray_in, hit_rec, &direction), pdf))
+    }
 
-        if scatter_direction.near_zero() {
-            scatter_direction = hit_rec.normal;
+    fn bsdf(&self, _: &Ray, hit_rec: &HitRecord, direction: &Vec3) -> Color {
+        match dot(direction, &hit_rec.normal) {
+            cos if cos > 0_f64 => self.tex.value(hit_rec.u, hit_rec.v, &hit_rec.p)/PI,
+            _ => Color::new_empty(),
         }
-        *scattered_ray = Ray::new(hit_rec.p, scatter_direction);
-        *attenuation = self.albedo;
-        true
+    }
+
+    fn pdf(&self, _: &Ray, hit_rec: &HitRecord, direction: &Vec3) -> f64 {
+        (dot(&unit_vector(direction), &hit_rec.normal)/PI).max(0_f64)
+    }
+
+    fn albedo(&self, hit_rec: &HitRecord) -> Color {
+        self.tex.value(hit_rec.u, hit_rec.v, &hit_rec.p)
     }
 }
 
 pub struct Metal {
-    albedo: Color,
+    tex: Arc<dyn Texture>,
     fuzz: f64,
+    id: u32,
 }
 
 impl Metal {
     pub fn new(color : Color, fuzz_factor: f64) -> Self  {
+        Self::from_texture(Arc::new(SolidColor::new(color)), fuzz_factor)
+    }
+
+    pub fn from_texture(tex: Arc<dyn Texture>, fuzz_factor: f64) -> Self {
         let result = if fuzz_factor < 1.0 { fuzz_factor } else { 1.0 };
         Self {
-        albedo: color,
+        tex,
         fuzz : result,
+        id : next_id(),
         }
     }
 }
 
+//the fuzzed reflection has no closed form pdf, so it's treated as specular
 impl Scatter for Metal {
-    fn scatter(&self, ray_in : &Ray, hit_rec: &HitRecord, attenuation: &mut Color, scattered_ray: &mut Ray) -> bool {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn scatter(&self, ray_in : &Ray, hit_rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord> {
         let mut reflected = reflect(&ray_in.dir(), &hit_rec.normal);
-        reflected = unit_vector(&reflected) + (self.fuzz * random_unit_vector());
-        *scattered_ray = Ray::new(hit_rec.p, reflected);
-        *attenuation = self.albedo;
-        dot(&scattered_ray.dir(), &hit_rec.normal) > 0_f64
+        reflected = unit_vector(&reflected) + (self.fuzz * random_unit_vector(sampler.get_2d()));
+        if dot(&reflected, &hit_rec.normal) <= 0_f64 {
+            return None;
+        }
+        Some(ScatterRecord::specular(reflected, self.tex.value(hit_rec.u, hit_rec.v, &hit_rec.p)))
+    }
+
+    fn albedo(&self, hit_rec: &HitRecord) -> Color {
+        self.tex.value(hit_rec.u, hit_rec.v, &hit_rec.p)
     }
 }
 
 pub struct Dielectric {
     refraction_idx : f64,
+    id : u32,
 }
 
 impl Dielectric {
     pub fn new(refraction_idx: f64) -> Self {
         Self {
             refraction_idx,
+            id : next_id(),
         }
     }
 
@@ -69,19 +119,62 @@ impl Dielectric {
 }
 
 impl Scatter for Dielectric {
-    fn scatter(&self, ray_in : &Ray, hit_rec: &HitRecord, attenuation: &mut Color, scattered_ray: &mut Ray) -> bool {
-        *attenuation = Color::new(1.0, 1.0, 1.0);
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn scatter(&self, ray_in : &Ray, hit_rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord> {
         let ri = if hit_rec.front_face { 1.0 / self.refraction_idx} else { self.refraction_idx};
         let unit_dir = unit_vector(&ray_in.dir());
         let cos_theta = dot(&(-unit_dir), &hit_rec.normal).min(1.0);
         let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
         
-        let direction = match ((ri * sin_theta > 1.0), self.reflectance(cos_theta) > random_f64()) {
+        let direction = match ((ri * sin_theta > 1.0), self.reflectance(cos_theta) > sampler.get_1d()) {
             (true, _) | (_, true) => reflect(&unit_dir, &hit_rec.normal),
             _ => refract(&unit_dir, &hit_rec.normal, ri),
         };
-        *scattered_ray = Ray::new(hit_rec.p, direction);
+        Some(ScatterRecord::specular(direction, Color::new(1.0, 1.0, 1.0)))
+    }
+
+    //clear glass passes everything through, which is what denoisers expect for its albedo
+    fn albedo(&self, _: &HitRecord) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+}
+
+//emits light from both sides of the surface and absorbs everything that hits it
+pub struct DiffuseLight {
+    tex: Arc<dyn Texture>,
+    id: u32,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> Self {
+        Self::from_texture(Arc::new(SolidColor::new(emit)))
+    }
+
+    pub fn from_texture(tex: Arc<dyn Texture>) -> Self {
+        Self {
+            tex,
+            id : next_id(),
+        }
+    }
+}
+
+impl Scatter for DiffuseLight {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn scatter(&self, _: &Ray, _: &HitRecord, _: &mut dyn Sampler) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, hit_rec: &HitRecord) -> Color {
+        self.tex.value(hit_rec.u, hit_rec.v, &hit_rec.p)
+    }
 
+    fn is_emissive(&self) -> bool {
         true
     }
 }
@@ -0,0 +1,185 @@
+use std::sync::Arc;
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use crate::{color::Color, texture::Texture, vec3::Point3};
+
+const PERM_SIZE: usize = 256;
+
+//improved gradient noise (Perlin 2002) over a permutation table shuffled from a seed, so the
+//same seed always gives the same pattern
+pub struct Perlin {
+    perm: [u8; 2*PERM_SIZE],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut table: Vec<u8> = (0..PERM_SIZE).map(|i| i as u8).collect();
+        table.shuffle(&mut StdRng::seed_from_u64(seed));
+
+        //doubled up so lookups of perm[perm[x] + y] never need wrapping
+        let mut perm = [0_u8; 2*PERM_SIZE];
+        for (i, p) in perm.iter_mut().enumerate() {
+            *p = table[i % PERM_SIZE];
+        }
+        Self {
+            perm,
+        }
+    }
+
+    //smooth noise in roughly [-1, 1], zero at every integer lattice point
+    pub fn noise(&self, p: &Point3) -> f64 {
+        let (fx, fy, fz) = (p.x().floor(), p.y().floor(), p.z().floor());
+        let (x, y, z) = (p.x() - fx, p.y() - fy, p.z() - fz);
+        let xi = (fx as i64).rem_euclid(PERM_SIZE as i64) as usize;
+        let yi = (fy as i64).rem_euclid(PERM_SIZE as i64) as usize;
+        let zi = (fz as i64).rem_euclid(PERM_SIZE as i64) as usize;
+
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+        let perm = &self.perm;
+        let a = perm[xi] as usize + yi;
+        let aa = perm[a] as usize + zi;
+        let ab = perm[a + 1] as usize + zi;
+        let b = perm[xi + 1] as usize + yi;
+        let ba = perm[b] as usize + zi;
+        let bb = perm[b + 1] as usize + zi;
+
+        lerp(w,
+            lerp(v,
+                lerp(u, grad(perm[aa], x, y, z), grad(perm[ba], x - 1_f64, y, z)),
+                lerp(u, grad(perm[ab], x, y - 1_f64, z), grad(perm[bb], x - 1_f64, y - 1_f64, z))),
+            lerp(v,
+                lerp(u, grad(perm[aa + 1], x, y, z - 1_f64), grad(perm[ba + 1], x - 1_f64, y, z - 1_f64)),
+                lerp(u, grad(perm[ab + 1], x, y - 1_f64, z - 1_f64), grad(perm[bb + 1], x - 1_f64, y - 1_f64, z - 1_f64))))
+    }
+
+    //fractal brownian motion, signed octaves of noise at increasing frequency and falling amplitude
+    pub fn fbm(&self, p: &Point3, octaves: u32, lacunarity: f64, gain: f64) -> f64 {
+        let mut sum = 0_f64;
+        let mut point = *p;
+        let mut weight = 1_f64;
+        for _ in 0..octaves {
+            sum += weight*self.noise(&point);
+            weight *= gain;
+            point = lacunarity*point;
+        }
+        sum
+    }
+
+    //sum of absolute octaves, giving the creased look used for marble veins
+    pub fn turbulence(&self, p: &Point3, depth: u32) -> f64 {
+        let mut sum = 0_f64;
+        let mut point = *p;
+        let mut weight = 1_f64;
+        for _ in 0..depth {
+            sum += weight*self.noise(&point).abs();
+            weight *= 0.5;
+            point = 2_f64*point;
+        }
+        sum
+    }
+}
+
+fn fade(t: f64) -> f64 {
+    t*t*t*(t*(t*6_f64 - 15_f64) + 10_f64)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t*(b - a)
+}
+
+//dot product with one of the 12 cube edge gradient directions picked by the hash
+fn grad(hash: u8, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = match h {
+        h if h < 4 => y,
+        12 | 14 => x,
+        _ => z,
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+//blends between two colors using fractal noise
+pub struct FbmTexture {
+    noise: Arc<Perlin>,
+    scale: f64,
+    octaves: u32,
+    low: Color,
+    high: Color,
+}
+
+impl FbmTexture {
+    pub fn new(noise: Arc<Perlin>, scale: f64, octaves: u32, low: Color, high: Color) -> Self {
+        Self {
+            noise,
+            scale,
+            octaves,
+            low,
+            high,
+        }
+    }
+}
+
+impl Texture for FbmTexture {
+    fn value(&self, _: f64, _: f64, p: &Point3) -> Color {
+        let n = self.noise.fbm(&(self.scale*(*p)), self.octaves, 2_f64, 0.5);
+        let t = (0.5*(1_f64 + n)).clamp(0_f64, 1_f64);
+        (1_f64 - t)*self.low + t*self.high
+    }
+}
+
+//sine bands along z, distorted by turbulence into veins
+pub struct MarbleTexture {
+    noise: Arc<Perlin>,
+    scale: f64,
+    base: Color,
+    vein: Color,
+}
+
+impl MarbleTexture {
+    pub fn new(noise: Arc<Perlin>, scale: f64, base: Color, vein: Color) -> Self {
+        Self {
+            noise,
+            scale,
+            base,
+            vein,
+        }
+    }
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _: f64, _: f64, p: &Point3) -> Color {
+        let t = 0.5*(1_f64 + (self.scale*p.z() + 10_f64*self.noise.turbulence(p, 7)).sin());
+        (1_f64 - t)*self.vein + t*self.base
+    }
+}
+
+//concentric growth rings around the y axis, wobbled with noise
+pub struct WoodTexture {
+    noise: Arc<Perlin>,
+    rings_per_unit: f64,
+    light: Color,
+    dark: Color,
+}
+
+impl WoodTexture {
+    pub fn new(noise: Arc<Perlin>, rings_per_unit: f64, light: Color, dark: Color) -> Self {
+        Self {
+            noise,
+            rings_per_unit,
+            light,
+            dark,
+        }
+    }
+}
+
+impl Texture for WoodTexture {
+    fn value(&self, _: f64, _: f64, p: &Point3) -> Color {
+        let r = (p.x()*p.x() + p.z()*p.z()).sqrt();
+        let rings = self.rings_per_unit*r + 0.5*self.noise.turbulence(&(2_f64*(*p)), 3);
+        //sharpen the ring edges so most of each ring is light wood
+        let t = (rings - rings.floor()).powi(3);
+        (1_f64 - t)*self.light + t*self.dark
+    }
+}
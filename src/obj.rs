@@ -0,0 +1,503 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{color::Color, colorspace::{ColorSpace, Mat3}, material::{Dielectric, DiffuseLight, Lambertian, Metal}, ray::{HittableList, Scatter}, triangle::{MeshFace, TriangleMesh}, vec3::{cross, dot, Point3, Vec3}};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io { path: PathBuf, source: io::Error },
+    Parse { path: PathBuf, line: usize, message: String },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse { path, line, message } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Parse { .. } => None,
+        }
+    }
+}
+
+//one mesh per group/material combination in the file
+pub struct ObjMesh {
+    pub group: String,
+    pub material: String,
+    pub mesh: Arc<TriangleMesh>,
+}
+
+pub struct ObjScene {
+    pub meshes: Vec<ObjMesh>,
+}
+
+impl ObjScene {
+    pub fn add_to(&self, list: &mut HittableList) {
+        for obj_mesh in &self.meshes {
+            for triangle in obj_mesh.mesh.triangles() {
+                list.add(triangle);
+            }
+        }
+    }
+}
+
+//loads a wavefront obj file along with any mtl libraries it references. faces without a
+//material (or naming one that was never defined) use `default_material`. mtl colors are taken
+//as srgb, like every tool writing them does, and converted into the `working` space
+pub fn load_obj(path: impl AsRef<Path>, default_material: Arc<dyn Scatter>, working: ColorSpace) -> Result<ObjScene, ObjError> {
+    let path = path.as_ref();
+    let source = read_file(path)?;
+
+    let mut parser = ObjParser {
+        path,
+        to_working: ColorSpace::LinearSrgb.conversion(working),
+        positions: Vec::new(),
+        normals: Vec::new(),
+        uvs: Vec::new(),
+        materials: HashMap::new(),
+        group: String::from("default"),
+        material: String::new(),
+        builders: Vec::new(),
+    };
+    for (line_no, line) in logical_lines(&source) {
+        parser.parse_line(line_no, line)?;
+    }
+
+    let meshes = parser.builders.into_iter()
+        .filter(|b| !b.faces.is_empty())
+        .map(|b| {
+            let mat = parser.materials.get(&b.material).cloned().unwrap_or_else(|| default_material.clone());
+            ObjMesh {
+                mesh: Arc::new(TriangleMesh::new(b.positions, b.normals, b.uvs, b.faces, mat)),
+                group: b.group,
+                material: b.material,
+            }
+        })
+        .collect();
+    Ok(ObjScene { meshes })
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|source| ObjError::Io { path: path.to_path_buf(), source })
+}
+
+//yields (1-based line number, line) with comments stripped and backslash continuations joined
+fn logical_lines(source: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut pending: Option<(usize, String)> = None;
+    for (i, raw) in source.lines().enumerate() {
+        let raw = raw.split('#').next().unwrap_or("");
+        let (start, mut text) = pending.take().unwrap_or((i + 1, String::new()));
+        match raw.trim_end().strip_suffix('\\') {
+            Some(head) => {
+                text.push_str(head);
+                text.push(' ');
+                pending = Some((start, text));
+            }
+            None => {
+                text.push_str(raw);
+                lines.push((start, text));
+            }
+        }
+    }
+    if let Some(line) = pending {
+        lines.push(line);
+    }
+    lines
+}
+
+fn parse_error(path: &Path, line: usize, message: impl Into<String>) -> ObjError {
+    ObjError::Parse { path: path.to_path_buf(), line, message: message.into() }
+}
+
+fn parse_f64(path: &Path, line: usize, token: Option<&str>, what: &str) -> Result<f64, ObjError> {
+    let token = token.ok_or_else(|| parse_error(path, line, format!("missing {}", what)))?;
+    token.parse::<f64>().map_err(|_| parse_error(path, line, format!("invalid number '{}' for {}", token, what)))
+}
+
+fn parse_vec3(path: &Path, line: usize, tokens: &mut std::str::SplitWhitespace) -> Result<Vec3, ObjError> {
+    let x = parse_f64(path, line, tokens.next(), "x")?;
+    let y = parse_f64(path, line, tokens.next(), "y")?;
+    let z = parse_f64(path, line, tokens.next(), "z")?;
+    Ok(Vec3::new(x, y, z))
+}
+
+#[derive(Clone, Copy)]
+struct FaceVertex {
+    p: u32,
+    uv: Option<u32>,
+    n: Option<u32>,
+}
+
+//faces for one group/material, with vertex attributes remapped into mesh-local arrays
+struct MeshBuilder {
+    group: String,
+    material: String,
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<[f64; 2]>,
+    position_map: HashMap<u32, u32>,
+    normal_map: HashMap<u32, u32>,
+    uv_map: HashMap<u32, u32>,
+    faces: Vec<MeshFace>,
+}
+
+struct ObjParser<'a> {
+    path: &'a Path,
+    to_working: Mat3,
+    positions: Vec<Point3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<[f64; 2]>,
+    materials: HashMap<String, Arc<dyn Scatter>>,
+    group: String,
+    material: String,
+    builders: Vec<MeshBuilder>,
+}
+
+impl ObjParser<'_> {
+    fn parse_line(&mut self, line_no: usize, line: String) -> Result<(), ObjError> {
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            return Ok(());
+        };
+        match keyword {
+            "v" => {
+                let p = parse_vec3(self.path, line_no, &mut tokens)?;
+                self.positions.push(p);
+            }
+            "vn" => {
+                let n = parse_vec3(self.path, line_no, &mut tokens)?;
+                self.normals.push(n);
+            }
+            "vt" => {
+                let u = parse_f64(self.path, line_no, tokens.next(), "u")?;
+                let v = match tokens.next() {
+                    Some(token) => parse_f64(self.path, line_no, Some(token), "v")?,
+                    None => 0_f64,
+                };
+                self.uvs.push([u, v]);
+            }
+            "f" => {
+                let corners = tokens
+                    .map(|token| self.parse_face_vertex(line_no, token))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if corners.len() < 3 {
+                    return Err(parse_error(self.path, line_no, "face needs at least 3 vertices"));
+                }
+                self.add_polygon(&corners);
+            }
+            "g" | "o" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                self.group = if name.is_empty() { String::from("default") } else { name };
+            }
+            "usemtl" => {
+                self.material = tokens.collect::<Vec<_>>().join(" ");
+            }
+            "mtllib" => {
+                let dir = self.path.parent().unwrap_or(Path::new(""));
+                for lib in tokens {
+                    let materials = load_mtl(&dir.join(lib), &self.to_working)?;
+                    self.materials.extend(materials);
+                }
+            }
+            //smoothing groups, lines, points, free-form geometry and the like are not supported
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn parse_face_vertex(&self, line_no: usize, token: &str) -> Result<FaceVertex, ObjError> {
+        let mut parts = token.split('/');
+        let p = parts.next().unwrap_or("");
+        let uv = parts.next().filter(|s| !s.is_empty());
+        let n = parts.next().filter(|s| !s.is_empty());
+
+        Ok(FaceVertex {
+            p: self.resolve_index(line_no, p, self.positions.len(), "vertex")?,
+            uv: uv.map(|s| self.resolve_index(line_no, s, self.uvs.len(), "texture coordinate")).transpose()?,
+            n: n.map(|s| self.resolve_index(line_no, s, self.normals.len(), "normal")).transpose()?,
+        })
+    }
+
+    //obj indices are 1-based, negative ones count back from the most recently defined element
+    fn resolve_index(&self, line_no: usize, token: &str, count: usize, what: &str) -> Result<u32, ObjError> {
+        let idx: i64 = token.parse()
+            .map_err(|_| parse_error(self.path, line_no, format!("invalid {} index '{}'", what, token)))?;
+        let resolved = match idx {
+            i if i > 0 => i - 1,
+            i if i < 0 => count as i64 + i,
+            _ => -1,
+        };
+        if resolved < 0 || resolved >= count as i64 {
+            return Err(parse_error(self.path, line_no, format!("{} index {} out of range ({} defined)", what, idx, count)));
+        }
+        Ok(resolved as u32)
+    }
+
+    fn add_polygon(&mut self, corners: &[FaceVertex]) {
+        let points: Vec<Point3> = corners.iter().map(|c| self.positions[c.p as usize]).collect();
+        let triangles = triangulate(&points);
+
+        let builder_idx = self.current_builder();
+        for [a, b, c] in triangles {
+            let tri = [corners[a], corners[b], corners[c]];
+            let builder = &mut self.builders[builder_idx];
+
+            let positions = tri.map(|v| remap(&mut builder.position_map, &mut builder.positions, &self.positions, v.p));
+            let normals = match tri.map(|v| v.n) {
+                [Some(n0), Some(n1), Some(n2)] => Some([n0, n1, n2].map(|n| remap(&mut builder.normal_map, &mut builder.normals, &self.normals, n))),
+                _ => None,
+            };
+            let uvs = match tri.map(|v| v.uv) {
+                [Some(t0), Some(t1), Some(t2)] => Some([t0, t1, t2].map(|t| remap(&mut builder.uv_map, &mut builder.uvs, &self.uvs, t))),
+                _ => None,
+            };
+            builder.faces.push(MeshFace { positions, normals, uvs });
+        }
+    }
+
+    fn current_builder(&mut self) -> usize {
+        if let Some(idx) = self.builders.iter().position(|b| b.group == self.group && b.material == self.material) {
+            return idx;
+        }
+        self.builders.push(MeshBuilder {
+            group: self.group.clone(),
+            material: self.material.clone(),
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            position_map: HashMap::new(),
+            normal_map: HashMap::new(),
+            uv_map: HashMap::new(),
+            faces: Vec::new(),
+        });
+        self.builders.len() - 1
+    }
+}
+
+fn remap<T: Copy>(map: &mut HashMap<u32, u32>, local: &mut Vec<T>, global: &[T], idx: u32) -> u32 {
+    *map.entry(idx).or_insert_with(|| {
+        local.push(global[idx as usize]);
+        (local.len() - 1) as u32
+    })
+}
+
+//splits a planar polygon into triangles by ear clipping in its dominant plane, so concave
+//faces come out right. degenerate polygons fall back to a fan
+fn triangulate(points: &[Point3]) -> Vec<[usize; 3]> {
+    let n = points.len();
+    if n == 3 {
+        return vec![[0, 1, 2]];
+    }
+
+    //newell's method gives a robust normal even for non-convex polygons
+    let mut normal = Vec3::new_empty();
+    for i in 0..n {
+        normal += cross(&points[i], &points[(i + 1) % n]);
+    }
+    let fan = || (1..n - 1).map(|i| [0, i, i + 1]).collect();
+    if normal.near_zero() {
+        return fan();
+    }
+
+    let mut remaining: Vec<usize> = (0..n).collect();
+    let mut triangles = Vec::with_capacity(n - 2);
+    while remaining.len() > 3 {
+        let m = remaining.len();
+        let ear = (0..m).find(|&i| {
+            let (a, b, c) = (remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]);
+            let (pa, pb, pc) = (points[a], points[b], points[c]);
+            if dot(&cross(&(pb - pa), &(pc - pb)), &normal) <= 0_f64 {
+                return false; //reflex corner
+            }
+            remaining.iter()
+                .filter(|&&j| j != a && j != b && j != c)
+                .all(|&j| !point_in_triangle(&points[j], &pa, &pb, &pc, &normal))
+        });
+        let Some(i) = ear else {
+            return fan();
+        };
+        triangles.push([remaining[(i + m - 1) % m], remaining[i], remaining[(i + 1) % m]]);
+        remaining.remove(i);
+    }
+    triangles.push([remaining[0], remaining[1], remaining[2]]);
+    triangles
+}
+
+fn point_in_triangle(p: &Point3, a: &Point3, b: &Point3, c: &Point3, normal: &Vec3) -> bool {
+    dot(&cross(&(*b - *a), &(*p - *a)), normal) >= 0_f64
+        && dot(&cross(&(*c - *b), &(*p - *b)), normal) >= 0_f64
+        && dot(&cross(&(*a - *c), &(*p - *c)), normal) >= 0_f64
+}
+
+struct MtlEntry {
+    diffuse: Color,
+    specular: Color,
+    emission: Color,
+    shininess: f64,
+    ior: f64,
+    dissolve: f64,
+    illum: i32,
+}
+
+impl MtlEntry {
+    fn new() -> Self {
+        Self {
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: Color::new_empty(),
+            emission: Color::new_empty(),
+            shininess: 0_f64,
+            ior: 1.5,
+            dissolve: 1_f64,
+            illum: 2,
+        }
+    }
+
+    //maps the phong style mtl parameters onto the closest material we have, with the colors
+    //taken into the working space by `to_working`
+    fn to_material(&self, to_working: &Mat3) -> Arc<dyn Scatter> {
+        if !self.emission.near_zero() {
+            return Arc::new(DiffuseLight::new(to_working.transform(&self.emission)));
+        }
+        match self.illum {
+            4 | 6 | 7 | 9 => Arc::new(Dielectric::new(self.ior)),
+            _ if self.dissolve < 1_f64 => Arc::new(Dielectric::new(self.ior)),
+            3 | 5 | 8 => {
+                //rough conversion from a phong exponent to a fuzz radius
+                let fuzz = (2_f64/(self.shininess + 2_f64)).sqrt();
+                Arc::new(Metal::new(to_working.transform(&self.specular), fuzz))
+            }
+            _ => Arc::new(Lambertian::new(to_working.transform(&self.diffuse))),
+        }
+    }
+}
+
+fn load_mtl(path: &Path, to_working: &Mat3) -> Result<HashMap<String, Arc<dyn Scatter>>, ObjError> {
+    let source = read_file(path)?;
+    let mut entries: Vec<(String, MtlEntry)> = Vec::new();
+
+    for (line_no, line) in logical_lines(&source) {
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        if keyword == "newmtl" {
+            let name = tokens.collect::<Vec<_>>().join(" ");
+            entries.push((name, MtlEntry::new()));
+            continue;
+        }
+
+        let entry = match entries.last_mut() {
+            Some((_, entry)) => entry,
+            None => return Err(parse_error(path, line_no, format!("'{}' before any newmtl", keyword))),
+        };
+        match keyword {
+            "Kd" => entry.diffuse = parse_vec3(path, line_no, &mut tokens)?,
+            "Ks" => entry.specular = parse_vec3(path, line_no, &mut tokens)?,
+            "Ke" => entry.emission = parse_vec3(path, line_no, &mut tokens)?,
+            "Ns" => entry.shininess = parse_f64(path, line_no, tokens.next(), "Ns")?,
+            "Ni" => entry.ior = parse_f64(path, line_no, tokens.next(), "Ni")?,
+            "d" => entry.dissolve = parse_f64(path, line_no, tokens.next(), "d")?,
+            "Tr" => entry.dissolve = 1_f64 - parse_f64(path, line_no, tokens.next(), "Tr")?,
+            "illum" => {
+                let token = tokens.next().unwrap_or("");
+                entry.illum = token.parse()
+                    .map_err(|_| parse_error(path, line_no, format!("invalid illumination model '{}'", token)))?;
+            }
+            //ambient terms, texture maps and other extensions are ignored
+            _ => {}
+        }
+    }
+
+    Ok(entries.into_iter().map(|(name, entry)| (name, entry.to_material(to_working))).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{material::Lambertian, test_util::TempPath};
+
+    //writes `files` into a fresh temp directory and loads the first one
+    fn load(test: &str, files: &[(&str, &str)]) -> Result<ObjScene, ObjError> {
+        let dir = TempPath::new(&format!("obj-{}", test));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        let material = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        load_obj(dir.join(files[0].0), material, ColorSpace::LinearSrgb)
+    }
+
+    //line and file of a parse error, failing the test for anything else
+    fn error_location(result: Result<ObjScene, ObjError>) -> (String, usize) {
+        match result {
+            Err(ObjError::Parse { path, line, .. }) => (path.file_name().unwrap().to_string_lossy().into_owned(), line),
+            Err(e) => panic!("expected a parse error, got {}", e),
+            Ok(_) => panic!("expected a parse error, the file loaded"),
+        }
+    }
+
+    const TRIANGLE: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\n";
+
+    #[test]
+    fn loads_faces_per_group() {
+        let scene = load("groups", &[("a.obj", "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\ng first\nf 1 2 3 4\ng second\nf -3 -2 -1\n")]).unwrap();
+        let faces: Vec<(&str, usize)> = scene.meshes.iter().map(|m| (m.group.as_str(), m.mesh.faces().len())).collect();
+        assert_eq!(faces, [("first", 2), ("second", 1)]);
+    }
+
+    #[test]
+    fn invalid_number_reports_its_line() {
+        let result = load("number", &[("a.obj", "# comment\nv 0 0 0\n\nv 1 x 0\n")]);
+        assert_eq!(error_location(result), (String::from("a.obj"), 4));
+    }
+
+    #[test]
+    fn out_of_range_index_reports_its_line() {
+        let obj = format!("{}f 1 2 3\nf 1 2 4\n", TRIANGLE);
+        assert_eq!(error_location(load("index", &[("a.obj", &obj)])), (String::from("a.obj"), 5));
+        let obj = format!("{}f 1/1 2/1 3/1\n", TRIANGLE);
+        assert_eq!(error_location(load("uv", &[("a.obj", &obj)])), (String::from("a.obj"), 4));
+        let obj = format!("{}f 0 1 2\n", TRIANGLE);
+        assert_eq!(error_location(load("zero", &[("a.obj", &obj)])), (String::from("a.obj"), 4));
+    }
+
+    #[test]
+    fn degenerate_face_reports_its_line() {
+        let obj = format!("{}f 1 2\n", TRIANGLE);
+        assert_eq!(error_location(load("degenerate", &[("a.obj", &obj)])), (String::from("a.obj"), 4));
+    }
+
+    #[test]
+    fn continued_lines_report_where_they_start() {
+        let result = load("continued", &[("a.obj", "v 0 0 0\nv 1 \\\n y 0\n")]);
+        assert_eq!(error_location(result), (String::from("a.obj"), 2));
+    }
+
+    #[test]
+    fn mtl_errors_report_the_mtl_file() {
+        let obj = format!("mtllib a.mtl\n{}usemtl red\nf 1 2 3\n", TRIANGLE);
+        let result = load("mtl", &[("a.obj", &obj), ("a.mtl", "newmtl red\nKd 1 0 0\nNs shiny\n")]);
+        assert_eq!(error_location(result), (String::from("a.mtl"), 3));
+        let result = load("mtl-order", &[("a.obj", &obj), ("a.mtl", "Kd 1 0 0\n")]);
+        assert_eq!(error_location(result), (String::from("a.mtl"), 1));
+    }
+
+    #[test]
+    fn missing_mtl_is_an_io_error() {
+        let obj = format!("mtllib missing.mtl\n{}", TRIANGLE);
+        assert!(matches!(load("missing", &[("a.obj", &obj)]), Err(ObjError::Io { .. })));
+    }
+}
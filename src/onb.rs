@@ -0,0 +1,41 @@
+use crate::vec3::{unit_vector, Vec3};
+
+//orthonormal basis with w along a given direction, for turning local samples (z up) into world space
+#[derive(Clone, Copy, Debug)]
+pub struct Onb {
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+}
+
+impl Onb {
+    //duff et al.'s branchless construction, "building an orthonormal basis, revisited"
+    pub fn new(n: &Vec3) -> Self {
+        let w = unit_vector(n);
+        let sign = 1_f64.copysign(w.z());
+        let a = -1_f64/(sign + w.z());
+        let b = w.x()*w.y()*a;
+        Self {
+            u: Vec3::new(1_f64 + sign*w.x()*w.x()*a, sign*b, -sign*w.x()),
+            v: Vec3::new(b, sign + w.y()*w.y()*a, -w.y()),
+            w,
+        }
+    }
+
+    pub fn u(&self) -> Vec3 {
+        self.u
+    }
+
+    pub fn v(&self) -> Vec3 {
+        self.v
+    }
+
+    pub fn w(&self) -> Vec3 {
+        self.w
+    }
+
+    //local coordinates to world space
+    pub fn transform(&self, a: &Vec3) -> Vec3 {
+        a.x()*self.u + a.y()*self.v + a.z()*self.w
+    }
+}
@@ -0,0 +1,312 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::{aov::Aov, color::{linear_to_srgb, write_color}, colorspace::ColorSpace, exr::{ExrCompression, ExrImage, ExrPixelType}, image::Image, input::ImageError};
+
+//encodes the image as an ascii P3 ppm, with the srgb transfer function unless `raw` is set
+pub fn write_ppm(image: &Image, raw: bool, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "P3")?;
+    writeln!(out, "{} {}", image.width(), image.height())?;
+    writeln!(out, "255")?;
+    for color in image.pixels() {
+        match raw {
+            true => {
+                let [r, g, b] = [color.x(), color.y(), color.z()].map(|c| quantize(c.clamp(0_f64, 1_f64), 255_f64));
+                writeln!(out, "{} {} {}", r, g, b)?;
+            }
+            false => write_color(out, color)?,
+        }
+    }
+    Ok(())
+}
+
+//encodes the image as a color pfm: little endian 32 bit floats, rows from the bottom up, with
+//the linear values written exactly as rendered
+pub fn write_pfm(image: &Image, out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "PF")?;
+    writeln!(out, "{} {}", image.width(), image.height())?;
+    writeln!(out, "-1.0")?;
+    for y in (0..image.height()).rev() {
+        for x in 0..image.width() {
+            let color = image.get(x, y);
+            for c in [color.x(), color.y(), color.z()] {
+                out.write_all(&(c as f32).to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+//encodes the image as openexr with the linear values as rendered. the image goes into the r, g, b
+//channels, `alpha` into a, and each of `layers` into channels prefixed with the aov name. exr
+//alpha is premultiplied, so the image should be rendered with a transparent background
+pub fn write_exr(image: &Image, alpha: Option<&Image>, layers: &[(Aov, &Image)], pixel_type: ExrPixelType, compression: ExrCompression, color_space: ColorSpace, out: impl Write) -> io::Result<()> {
+    let mut exr = ExrImage::new(image.width(), image.height());
+    exr.compression = compression;
+    exr.color_space = color_space;
+    exr.add_layer("", image, &["R", "G", "B"], pixel_type)?;
+    if let Some(alpha) = alpha {
+        exr.add_layer("", alpha, &["A"], pixel_type)?;
+    }
+    for (aov, layer) in layers {
+        //ids would lose precision as floats
+        let pixel_type = if aov.is_id() { ExrPixelType::Uint } else { pixel_type };
+        exr.add_layer(aov.name(), layer, aov.channels(), pixel_type)?;
+    }
+    exr.write(out)
+}
+
+//bits per channel, for formats that have a choice
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitDepth {
+    Eight,
+    Sixteen,
+    ThirtyTwo,
+}
+
+//how images get encoded, formats ignore the settings that don't apply to them
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OutputSettings {
+    pub bit_depth: Option<BitDepth>, //the format's default when none
+    pub compression: ExrCompression,
+    pub color_space: ColorSpace, //space the pixels are in, recorded by formats that have a place for it
+    pub raw: bool, //ldr formats store the values without the srgb transfer function, for data that isn't a color
+}
+
+//divides the colors by the first channel of `alpha` where that's above 0, turning an image
+//rendered with a transparent background into the straight colors png alpha goes with
+pub fn unpremultiply(image: &mut Image, alpha: &Image) {
+    for (color, a) in image.pixels_mut().iter_mut().zip(alpha.pixels()) {
+        if a.x() > 0_f64 {
+            *color = (1_f64/a.x().min(1_f64))*(*color);
+        }
+    }
+}
+
+//encodes the image as a png with the srgb transfer function. `alpha` (e.g. the coverage aov) is
+//written as straight alpha from its first channel, see unpremultiply for the colors. images in
+//other spaces than srgb are tagged with their primaries and an approximate gamma instead. `raw` skips the transfer
+//function and tags the file as linear
+pub fn write_png(image: &Image, alpha: Option<&Image>, bit_depth: BitDepth, color_space: ColorSpace, raw: bool, out: impl Write) -> io::Result<()> {
+    if alpha.is_some_and(|alpha| alpha.width() != image.width() || alpha.height() != image.height()) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "alpha has different dimensions than the image"));
+    }
+
+    let mut encoder = png::Encoder::new(out, image.width() as u32, image.height() as u32);
+    encoder.set_color(if alpha.is_some() { png::ColorType::Rgba } else { png::ColorType::Rgb });
+    encoder.set_depth(match bit_depth {
+        BitDepth::Eight => png::BitDepth::Eight,
+        BitDepth::Sixteen => png::BitDepth::Sixteen,
+        BitDepth::ThirtyTwo => return Err(io::Error::new(io::ErrorKind::InvalidInput, "png supports 8 or 16 bits per channel")),
+    });
+    match (color_space, raw) {
+        (ColorSpace::LinearSrgb, false) => encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual),
+        _ => {
+            let c = color_space.chromaticities();
+            let xy = |v: [f64; 2]| (v[0] as f32, v[1] as f32);
+            encoder.set_source_chromaticities(png::SourceChromaticities::new(xy(c.white), xy(c.red), xy(c.green), xy(c.blue)));
+            encoder.set_source_gamma(png::ScaledFloat::new(if raw { 1_f32 } else { 1_f32/2.2 }));
+        }
+    }
+    let transfer = |c: f64| if raw { c } else { linear_to_srgb(c) };
+
+    let mut data = Vec::new();
+    let mut push = |v: f64| match bit_depth {
+        BitDepth::Eight => data.push(quantize(v, 255_f64) as u8),
+        BitDepth::Sixteen => data.extend_from_slice(&(quantize(v, 65535_f64) as u16).to_be_bytes()),
+        BitDepth::ThirtyTwo => unreachable!("rejected when setting the png bit depth"),
+    };
+    for (i, color) in image.pixels().iter().enumerate() {
+        for c in [color.x(), color.y(), color.z()] {
+            push(transfer(c.clamp(0_f64, 1_f64)));
+        }
+        if let Some(alpha) = alpha {
+            push(alpha.pixels()[i].x().clamp(0_f64, 1_f64));
+        }
+    }
+
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&data).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+//formats images can be saved in, picked from the file extension
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+    Pfm,
+    Exr,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            "pfm" => Some(ImageFormat::Pfm),
+            "exr" => Some(ImageFormat::Exr),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Png => "png",
+            ImageFormat::Pfm => "pfm",
+            ImageFormat::Exr => "exr",
+        }
+    }
+
+    //whether the format keeps linear values beyond [0, 1] as they are
+    pub fn is_hdr(&self) -> bool {
+        matches!(self, ImageFormat::Pfm | ImageFormat::Exr)
+    }
+
+    //whether the format has an alpha channel for --alpha to go into
+    pub fn has_alpha(&self) -> bool {
+        matches!(self, ImageFormat::Png | ImageFormat::Exr)
+    }
+
+    //whether aov layers can be stored in the same file as the image
+    pub fn has_layers(&self) -> bool {
+        matches!(self, ImageFormat::Exr)
+    }
+
+    fn default_bit_depth(&self) -> BitDepth {
+        match self {
+            ImageFormat::Ppm | ImageFormat::Png => BitDepth::Eight,
+            ImageFormat::Exr => BitDepth::Sixteen,
+            ImageFormat::Pfm => BitDepth::ThirtyTwo,
+        }
+    }
+}
+
+//saves the image in the format matching the path's extension. alpha and `layers` are dropped by
+//formats that can't store them, see ImageFormat::has_layers. the pixels have to be in
+//`settings.color_space` already
+pub fn write_image(path: impl AsRef<Path>, image: &Image, alpha: Option<&Image>, layers: &[(Aov, &Image)], settings: &OutputSettings) -> Result<(), ImageError> {
+    let path = path.as_ref();
+    let format_error = |message: &str| ImageError::Format { path: path.to_path_buf(), message: String::from(message) };
+    let Some(format) = ImageFormat::from_path(path) else {
+        return Err(format_error("unsupported output format, expected .ppm, .png, .pfm or .exr"));
+    };
+    let bit_depth = settings.bit_depth.unwrap_or(format.default_bit_depth());
+    let exr_pixel_type = match (format, bit_depth) {
+        (ImageFormat::Png, BitDepth::ThirtyTwo) => return Err(format_error("png supports 8 or 16 bits per channel")),
+        (ImageFormat::Exr, BitDepth::Eight) => return Err(format_error("exr supports 16 (half) or 32 (float) bits per channel")),
+        (_, BitDepth::ThirtyTwo) => ExrPixelType::Float,
+        _ => ExrPixelType::Half,
+    };
+
+    let io_error = |source| ImageError::Io { path: path.to_path_buf(), source };
+    let mut out = BufWriter::new(File::create(path).map_err(io_error)?);
+    match format {
+        ImageFormat::Ppm => write_ppm(image, settings.raw, &mut out),
+        ImageFormat::Png => write_png(image, alpha, bit_depth, settings.color_space, settings.raw, &mut out),
+        ImageFormat::Pfm => write_pfm(image, &mut out),
+        ImageFormat::Exr => write_exr(image, alpha, layers, exr_pixel_type, settings.compression, settings.color_space, &mut out),
+    }.and_then(|_| out.flush()).map_err(io_error)
+}
+
+fn quantize(v: f64, max: f64) -> f64 {
+    (v*max + 0.5).floor().min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, test_util::TempPath};
+
+    #[test]
+    fn pfm_header_and_row_order() {
+        let mut image = Image::new(1, 2);
+        image.set(0, 0, Color::new(1_f64, 2_f64, 3_f64));
+        image.set(0, 1, Color::new(-0.5, 1e30, 0_f64));
+        let mut bytes = Vec::new();
+        write_pfm(&image, &mut bytes).unwrap();
+
+        let mut expected = b"PF\n1 2\n-1.0\n".to_vec();
+        //bottom row first, little endian as the negative scale says
+        for v in [-0.5_f32, 1e30, 0_f32, 1_f32, 2_f32, 3_f32] {
+            expected.extend_from_slice(&v.to_le_bytes());
+        }
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn ppm_header_and_encoding() {
+        let mut image = Image::new(2, 1);
+        image.set(0, 0, Color::new(0_f64, 1_f64, 2_f64));
+        image.set(1, 0, Color::new(0.5, 0.25, -1_f64));
+        let mut bytes = Vec::new();
+        write_ppm(&image, false, &mut bytes).unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(), "P3\n2 1\n255\n0 255 255\n188 137 0\n");
+        let mut bytes = Vec::new();
+        write_ppm(&image, true, &mut bytes).unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(), "P3\n2 1\n255\n0 255 255\n128 64 0\n");
+    }
+
+    #[test]
+    fn exr_header_holds_alpha_and_layers() {
+        let image = Image::new(2, 2);
+        let mut bytes = Vec::new();
+        write_exr(&image, Some(&image), &[(Aov::MaterialId, &image), (Aov::Depth, &image)], ExrPixelType::Half, ExrCompression::None, ColorSpace::Rec2020, &mut bytes).unwrap();
+        let channel = |name: &str, code: u8| {
+            let mut entry = name.as_bytes().to_vec();
+            entry.extend_from_slice(&[0, code, 0, 0, 0]);
+            bytes.windows(entry.len()).any(|w| w == entry)
+        };
+        assert!(channel("A", 1) && channel("R", 1) && channel("depth.Z", 1));
+        //ids are stored as uint whatever the requested pixel type
+        assert!(channel("material.id", 0));
+    }
+
+    #[test]
+    fn png_keeps_straight_alpha() {
+        let mut image = Image::new(2, 1);
+        let mut alpha = Image::new(2, 1);
+        //a pixel half covered by white, premultiplied as rendered, and an empty one
+        image.set(0, 0, Color::new(0.5, 0.5, 0.5));
+        alpha.set(0, 0, Color::new(0.5, 0.5, 0.5));
+        unpremultiply(&mut image, &alpha);
+        let mut bytes = Vec::new();
+        write_png(&image, Some(&alpha), BitDepth::Eight, ColorSpace::LinearSrgb, false, &mut bytes).unwrap();
+
+        let mut reader = png::Decoder::new(bytes.as_slice()).read_info().unwrap();
+        let mut pixels = vec![0_u8; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+        assert_eq!(pixels, [255, 255, 255, 128, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn png_rejects_32_bits() {
+        let image = Image::new(1, 1);
+        assert!(write_png(&image, None, BitDepth::ThirtyTwo, ColorSpace::LinearSrgb, false, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn formats_from_paths() {
+        assert_eq!(ImageFormat::from_path("render.EXR"), Some(ImageFormat::Exr));
+        assert_eq!(ImageFormat::from_path("dir.png/render.pfm"), Some(ImageFormat::Pfm));
+        assert_eq!(ImageFormat::from_path("render.tiff"), None);
+        assert_eq!(ImageFormat::from_path("render"), None);
+    }
+
+    #[test]
+    fn write_image_rejects_unsupported_settings() {
+        let image = Image::new(1, 1);
+        for (ext, bit_depth) in [("png", BitDepth::ThirtyTwo), ("exr", BitDepth::Eight)] {
+            let path = TempPath::new(&format!("output-unsupported.{}", ext));
+            let settings = OutputSettings { bit_depth: Some(bit_depth), ..OutputSettings::default() };
+            assert!(matches!(write_image(&path, &image, None, &[], &settings), Err(ImageError::Format { .. })));
+            assert!(!path.exists());
+        }
+        let path = TempPath::new("output-unsupported.bmp");
+        assert!(matches!(write_image(&path, &image, None, &[], &OutputSettings::default()), Err(ImageError::Format { .. })));
+    }
+}
@@ -0,0 +1,85 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+//a finished block of pixels, in image coordinates with y going down
+#[derive(Clone, Copy, Debug)]
+pub struct TileRect {
+    pub x0: usize,
+    pub y0: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+//snapshot of how far a render has got. a plain render is a single pass; progressive renders
+//count tiles per pass and only know `passes_total` when they have a target sample count
+#[derive(Clone, Copy, Debug)]
+pub struct RenderProgress {
+    pub tiles_done: usize,
+    pub tiles_total: usize,
+    pub pass: u32,
+    pub passes_total: Option<u32>,
+    pub elapsed: Duration,
+}
+
+impl RenderProgress {
+    //fraction of the whole render that is done, or of the current pass when the pass count is unknown
+    pub fn fraction(&self) -> f64 {
+        let pass_fraction = match self.tiles_total {
+            0 => 1_f64,
+            total => self.tiles_done as f64/total as f64,
+        };
+        match self.passes_total {
+            Some(0) => 1_f64,
+            Some(passes) => ((self.pass as f64 + pass_fraction)/passes as f64).min(1_f64),
+            None => pass_fraction,
+        }
+    }
+
+    //extrapolates the time left from the rate so far. none until something has finished, and
+    //none after the first pass when the pass count is unknown since `elapsed` spans every pass
+    pub fn eta(&self) -> Option<Duration> {
+        if self.passes_total.is_none() && self.pass > 0 {
+            return None;
+        }
+        let fraction = self.fraction();
+        if fraction <= 0_f64 {
+            return None;
+        }
+        Some(self.elapsed.mul_f64((1_f64 - fraction)/fraction))
+    }
+}
+
+//receives progress from the render threads, so calls can come from several threads at once
+pub trait ProgressObserver: Send + Sync {
+    fn tile_finished(&self, tile: &TileRect, progress: &RenderProgress);
+    fn render_finished(&self, _progress: &RenderProgress, _cancelled: bool) {}
+}
+
+//the default observer, renders quietly
+pub struct SilentProgress;
+
+impl ProgressObserver for SilentProgress {
+    fn tile_finished(&self, _tile: &TileRect, _progress: &RenderProgress) {}
+}
+
+//shared flag to stop a render early. clones refer to the same flag, so one can be handed to
+//another thread or a signal handler while the camera holds the other
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
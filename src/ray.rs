@@ -1,16 +1,21 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
-use crate::{color::Color, interval::{Interval}, vec3::{dot, Point3}, Vec3};
+use crate::{aabb::Aabb, color::Color, sampler::Sampler, interval::{Interval}, vec3::{dot, unit_vector, Point3, Vec3}};
 
 #[derive(Clone)]
 pub struct HitRecord {
     pub p : Point3,
     pub normal: Vec3,
-    pub mat: Option<Rc<dyn Scatter>>,
+    pub mat: Option<Arc<dyn Scatter>>,
     pub t : f64,
+    pub u : f64,
+    pub v : f64,
     pub front_face : bool,
+    pub bary : Vec3, //barycentric weights of the hit point on triangles, zero for other shapes
+    pub object_id : u32, //index of the hit object in the outermost list or bvh it was added to
 }
 
+#[derive(Clone, Copy, Debug)]
 pub struct Ray {
     origin: Point3,
     dir: Vec3,
@@ -44,8 +49,41 @@ impl Ray {
     }
 }
 
-pub trait Hittable {
+pub trait Hittable: Send + Sync {
     fn hit(&self, ray :&Ray, ray_t: Interval, rec: &mut HitRecord) -> bool;
+    fn bounding_box(&self) -> Aabb;
+
+    //true for primitives with an emissive material, so they get sampled directly as lights
+    fn is_light(&self) -> bool {
+        false
+    }
+
+    //every light below this hittable, for aggregates holding other hittables
+    fn lights(&self) -> Vec<Arc<dyn Hittable>> {
+        Vec::new()
+    }
+
+    //direction from `origin` to a point on the shape picked with `u`, only called on lights
+    fn sample_direction(&self, _origin: &Point3, _u: [f64; 2]) -> Vec3 {
+        Vec3::new(1_f64, 0_f64, 0_f64)
+    }
+
+    //solid angle density with which `sample_direction` picks `direction`, zero if it misses the shape
+    fn pdf_value(&self, _origin: &Point3, _direction: &Vec3) -> f64 {
+        0_f64
+    }
+}
+
+//the lights among `objects` and everything below them
+pub(crate) fn gather_lights(objects: &[Arc<dyn Hittable>]) -> Vec<Arc<dyn Hittable>> {
+    let mut lights = Vec::new();
+    for object in objects {
+        match object.is_light() {
+            true => lights.push(object.clone()),
+            false => lights.extend(object.lights()),
+        }
+    }
+    lights
 }
 
 pub trait SetFaceNormal {
@@ -69,24 +107,45 @@ impl HitRecord {
             normal : Vec3::new(0_f64, 0_f64, 0_f64),
             mat : None,
             t : 0_f64,
+            u : 0_f64,
+            v : 0_f64,
             front_face : false,
+            bary : Vec3::new_empty(),
+            object_id : 0,
         }
     }
 }
 
 pub struct HittableList {
-    objects : Vec<Rc<dyn Hittable>>,
+    objects : Vec<Arc<dyn Hittable>>,
+    bbox : Aabb,
 }
 
 impl HittableList {
     pub fn new() -> Self {
         Self {
-            objects : Vec::new()
+            objects : Vec::new(),
+            bbox : Aabb::EMPTY,
         }
     }
-    pub fn add(&mut self, object:Rc<dyn Hittable>) {
+    pub fn add(&mut self, object:Arc<dyn Hittable>) {
+        self.bbox = Aabb::surrounding(&self.bbox, &object.bounding_box());
         self.objects.push(object);
     }
+
+    pub fn objects(&self) -> &[Arc<dyn Hittable>] {
+        &self.objects
+    }
+
+    pub fn into_objects(self) -> Vec<Arc<dyn Hittable>> {
+        self.objects
+    }
+}
+
+impl Default for HittableList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Hittable for HittableList {
@@ -95,17 +154,89 @@ impl Hittable for HittableList {
         let mut hit_anything : bool = false;
         let mut closest_so_far = ray_t.max;
 
-        for object in &self.objects {
+        for (idx, object) in self.objects.iter().enumerate() {
             if object.hit(ray, Interval::new(ray_t.min, closest_so_far), &mut temp_rec) {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
+                temp_rec.object_id = idx as u32;
                 *rec = temp_rec.clone()
             }
         }
         hit_anything
     }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn lights(&self) -> Vec<Arc<dyn Hittable>> {
+        gather_lights(&self.objects)
+    }
 }
 
-pub trait Scatter {
-    fn scatter(&self, ray_in : &Ray, hit_rec: &HitRecord, attenuation: &mut Color, scattered_ray: &mut Ray) -> bool;
+//result of sampling a material. `bsdf` is the value of the bsdf for `direction` and `pdf` the
+//density it was sampled with; specular bounces are delta distributions with no usable pdf, so for
+//those `bsdf` is the attenuation carried straight through and `pdf` is unused
+#[derive(Clone, Copy, Debug)]
+pub struct ScatterRecord {
+    pub direction: Vec3,
+    pub bsdf: Color,
+    pub pdf: f64,
+    pub specular: bool,
+}
+
+impl ScatterRecord {
+    pub fn sampled(direction: Vec3, bsdf: Color, pdf: f64) -> Self {
+        Self { direction, bsdf, pdf, specular: false }
+    }
+
+    pub fn specular(direction: Vec3, attenuation: Color) -> Self {
+        Self { direction, bsdf: attenuation, pdf: 0_f64, specular: true }
+    }
+
+    //what the radiance arriving along `direction` gets multiplied by, bsdf*cos/pdf
+    pub fn weight(&self, normal: &Vec3) -> Color {
+        if self.specular {
+            return self.bsdf;
+        }
+        if self.pdf <= 0_f64 {
+            return Color::new_empty();
+        }
+        let cos_theta = dot(&unit_vector(&self.direction), normal).abs();
+        (cos_theta/self.pdf)*self.bsdf
+    }
+}
+
+pub trait Scatter: Send + Sync {
+    //shared by every shape using the material, never 0 so that stays free for the background
+    fn id(&self) -> u32;
+
+    //samples an outgoing direction, none when the path is absorbed
+    fn scatter(&self, ray_in : &Ray, hit_rec: &HitRecord, sampler: &mut dyn Sampler) -> Option<ScatterRecord>;
+
+    //bsdf for a direction picked by something other than `scatter`, e.g. towards a light.
+    //black for specular materials since a delta can't be hit by chance
+    fn bsdf(&self, _ray_in: &Ray, _hit_rec: &HitRecord, _direction: &Vec3) -> Color {
+        Color::new_empty()
+    }
+
+    //density with which `scatter` would have picked `direction`
+    fn pdf(&self, _ray_in: &Ray, _hit_rec: &HitRecord, _direction: &Vec3) -> f64 {
+        0_f64
+    }
+
+    //radiance given off by the surface at the hit point, black for anything that isn't a light
+    fn emitted(&self, _hit_rec: &HitRecord) -> Color {
+        Color::new_empty()
+    }
+
+    //whether shapes with this material should be sampled as lights
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    //base color at the hit point for the albedo aov, black unless a material says otherwise
+    fn albedo(&self, _hit_rec: &HitRecord) -> Color {
+        Color::new_empty()
+    }
 }
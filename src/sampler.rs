@@ -0,0 +1,618 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use crate::helper::{hash, hash_to_f64, mix_bits, random_f64, Rng};
+
+//every sampler hands out dimensions in the same order: the pixel position, the lens position,
+//then a fixed block per bounce, so a given dimension always feeds the same decision. each bounce
+//block starts with the bsdf sample, followed by the light sample at LIGHT_DIMENSION_OFFSET and
+//the russian roulette decision at ROULETTE_DIMENSION_OFFSET
+pub const PIXEL_DIMENSION: u32 = 0;
+pub const LENS_DIMENSION: u32 = 2;
+pub const FIRST_BOUNCE_DIMENSION: u32 = 4;
+pub const DIMENSIONS_PER_BOUNCE: u32 = 8;
+pub const LIGHT_DIMENSION_OFFSET: u32 = 4;
+pub const ROULETTE_DIMENSION_OFFSET: u32 = 7;
+
+pub fn bounce_dimension(bounce: u32) -> u32 {
+    FIRST_BOUNCE_DIMENSION + bounce*DIMENSIONS_PER_BOUNCE
+}
+
+//source of sample values in [0, 1) for one pixel sample at a time
+pub trait Sampler: Send {
+    fn start_pixel_sample(&mut self, x: i32, y: i32, index: u32);
+    fn set_dimension(&mut self, dim: u32);
+    fn get_1d(&mut self) -> f64;
+    fn get_2d(&mut self) -> [f64; 2];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+
+impl SamplerKind {
+    //number stored for the kind in checkpoints, which must never change for existing kinds
+    pub fn id(&self) -> u64 {
+        match self {
+            SamplerKind::Independent => 0,
+            SamplerKind::Stratified => 1,
+            SamplerKind::Halton => 2,
+            SamplerKind::Sobol => 3,
+            SamplerKind::BlueNoise => 4,
+        }
+    }
+
+    pub fn from_id(id: u64) -> Option<Self> {
+        match id {
+            0 => Some(SamplerKind::Independent),
+            1 => Some(SamplerKind::Stratified),
+            2 => Some(SamplerKind::Halton),
+            3 => Some(SamplerKind::Sobol),
+            4 => Some(SamplerKind::BlueNoise),
+            _ => None,
+        }
+    }
+
+    //`samples_per_pixel` lets the stratified samplers size their strata, later samples
+    //still work but start a new round of strata
+    pub fn create(&self, seed: u64, samples_per_pixel: u32) -> Box<dyn Sampler> {
+        let spp = samples_per_pixel.max(1);
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(seed, spp)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed, spp)),
+            SamplerKind::BlueNoise => Box::new(BlueNoiseSampler::new(seed, spp)),
+        }
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" | "random" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            "bluenoise" | "blue-noise" => Ok(SamplerKind::BlueNoise),
+            _ => Err(format!("unknown sampler '{}', expected independent, stratified, halton, sobol or bluenoise", s)),
+        }
+    }
+}
+
+impl fmt::Display for SamplerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+            SamplerKind::BlueNoise => "bluenoise",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+//pixel/sample/dimension bookkeeping shared by the samplers below
+#[derive(Clone, Copy)]
+struct SampleState {
+    seed: u64,
+    x: i32,
+    y: i32,
+    index: u32,
+    dim: u32,
+}
+
+impl SampleState {
+    fn new(seed: u64) -> Self {
+        Self { seed, x: 0, y: 0, index: 0, dim: 0 }
+    }
+
+    fn start(&mut self, x: i32, y: i32, index: u32) {
+        self.x = x;
+        self.y = y;
+        self.index = index;
+        self.dim = 0;
+    }
+
+    //hash unique to this pixel and dimension, shared by all samples of the pixel
+    fn pixel_hash(&self, dim: u32) -> u64 {
+        hash(&[self.seed, self.x as u64, self.y as u64, dim as u64])
+    }
+
+    //white noise value unique to this pixel, sample and dimension
+    fn random(&self, dim: u32) -> f64 {
+        hash_to_f64(hash(&[self.seed, self.x as u64, self.y as u64, self.index as u64, dim as u64]))
+    }
+}
+
+//uncorrelated white noise, the slowest to converge but free of any structure
+pub struct IndependentSampler {
+    seed: u64,
+    rng: Rng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, x: i32, y: i32, index: u32) {
+        self.rng = Rng::for_pixel_sample(self.seed, x, y, index);
+    }
+
+    //dimensions carry no meaning for white noise
+    fn set_dimension(&mut self, _: u32) {}
+
+    fn get_1d(&mut self) -> f64 {
+        random_f64(&mut self.rng)
+    }
+
+    fn get_2d(&mut self) -> [f64; 2] {
+        [random_f64(&mut self.rng), random_f64(&mut self.rng)]
+    }
+}
+
+//jittered strata, with each dimension's strata visited in an independent random order so
+//dimensions don't correlate with each other
+pub struct StratifiedSampler {
+    state: SampleState,
+    spp: u32,
+    columns: u32,
+    rows: u32,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: u32) -> Self {
+        let columns = (samples_per_pixel as f64).sqrt().ceil() as u32;
+        let rows = samples_per_pixel.div_ceil(columns);
+        Self {
+            state: SampleState::new(seed),
+            spp: samples_per_pixel,
+            columns,
+            rows,
+        }
+    }
+
+    //stratum for the current sample out of `count` strata, reshuffled every `spp` samples
+    fn stratum(&self, dim: u32, count: u32) -> u32 {
+        let round = self.state.index/self.spp;
+        let h = hash(&[self.state.pixel_hash(dim), round as u64]);
+        permutation_element(self.state.index % self.spp, count, h as u32)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: i32, y: i32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn set_dimension(&mut self, dim: u32) {
+        self.state.dim = dim;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dim = self.state.dim;
+        self.state.dim += 1;
+        let stratum = self.stratum(dim, self.spp);
+        (stratum as f64 + self.state.random(dim))/self.spp as f64
+    }
+
+    fn get_2d(&mut self) -> [f64; 2] {
+        let dim = self.state.dim;
+        self.state.dim += 2;
+        let stratum = self.stratum(dim, self.columns*self.rows);
+        [
+            ((stratum % self.columns) as f64 + self.state.random(dim))/self.columns as f64,
+            ((stratum/self.columns) as f64 + self.state.random(dim + 1))/self.rows as f64,
+        ]
+    }
+}
+
+const PRIMES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+    137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193, 197, 199, 211, 223,
+    227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307, 311,
+];
+
+//halton sequence with one prime base per dimension, owen scrambled per pixel so neighbouring
+//pixels don't share the same pattern. dimensions past the prime table fall back to white noise
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: SampleState::new(seed),
+        }
+    }
+
+    fn sample(&self, dim: u32) -> f64 {
+        match PRIMES.get(dim as usize) {
+            Some(&base) => owen_scrambled_radical_inverse(base, self.state.index as u64, self.state.pixel_hash(dim)),
+            None => self.state.random(dim),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: i32, y: i32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn set_dimension(&mut self, dim: u32) {
+        self.state.dim = dim;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dim = self.state.dim;
+        self.state.dim += 1;
+        self.sample(dim)
+    }
+
+    fn get_2d(&mut self) -> [f64; 2] {
+        let dim = self.state.dim;
+        self.state.dim += 2;
+        [self.sample(dim), self.sample(dim + 1)]
+    }
+}
+
+//padded sobol: every dimension (pair) gets the first one (two) sobol dimensions with its own
+//owen scrambling and sample order. works best when samples_per_pixel is a power of two
+pub struct SobolSampler {
+    state: SampleState,
+    spp: u32,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64, samples_per_pixel: u32) -> Self {
+        Self {
+            state: SampleState::new(seed),
+            spp: samples_per_pixel,
+        }
+    }
+
+    //shuffles the sample order within each block of spp samples
+    fn shuffled_index(&self, h: u64) -> u32 {
+        let block = self.state.index/self.spp;
+        block*self.spp + permutation_element(self.state.index % self.spp, self.spp, h as u32)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: i32, y: i32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn set_dimension(&mut self, dim: u32) {
+        self.state.dim = dim;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let h = self.state.pixel_hash(self.state.dim);
+        self.state.dim += 1;
+        let index = self.shuffled_index(h);
+        to_unit_f64(owen_scramble(sobol_0(index), (h >> 32) as u32))
+    }
+
+    fn get_2d(&mut self) -> [f64; 2] {
+        let h = self.state.pixel_hash(self.state.dim);
+        self.state.dim += 2;
+        let index = self.shuffled_index(h);
+        let h2 = mix_bits(h);
+        [
+            to_unit_f64(owen_scramble(sobol_0(index), (h >> 32) as u32)),
+            to_unit_f64(owen_scramble(sobol_1(index), (h2 >> 32) as u32)),
+        ]
+    }
+}
+
+//side of the tiled blue noise rank mask
+const BLUE_NOISE_SIZE: usize = 64;
+
+//low discrepancy sobol points, rotated per pixel by a blue noise mask (Georgiev and Fajardo
+//2016), so the error that remains is pushed into high frequencies the eye forgives easily
+pub struct BlueNoiseSampler {
+    state: SampleState,
+    spp: u32,
+    mask: &'static [f64],
+}
+
+impl BlueNoiseSampler {
+    pub fn new(seed: u64, samples_per_pixel: u32) -> Self {
+        Self {
+            state: SampleState::new(seed),
+            spp: samples_per_pixel,
+            mask: blue_noise_mask(),
+        }
+    }
+
+    //mask value for this pixel, offset per dimension so dimensions get decorrelated rotations
+    fn rotation(&self, dim: u32) -> f64 {
+        let h = hash(&[self.state.seed, dim as u64]);
+        let ox = (h & 0xffff) as usize;
+        let oy = ((h >> 16) & 0xffff) as usize;
+        let x = (self.state.x as usize).wrapping_add(ox) % BLUE_NOISE_SIZE;
+        let y = (self.state.y as usize).wrapping_add(oy) % BLUE_NOISE_SIZE;
+        self.mask[y*BLUE_NOISE_SIZE + x]
+    }
+
+    //the same index shuffle for every pixel, which keeps the blue noise relationship between
+    //neighbouring pixels intact
+    fn shuffled_index(&self, dim: u32) -> u32 {
+        let h = hash(&[self.state.seed, dim as u64, 0x6e]) as u32;
+        let block = self.state.index/self.spp;
+        block*self.spp + permutation_element(self.state.index % self.spp, self.spp, h)
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_pixel_sample(&mut self, x: i32, y: i32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn set_dimension(&mut self, dim: u32) {
+        self.state.dim = dim;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dim = self.state.dim;
+        self.state.dim += 1;
+        let index = self.shuffled_index(dim);
+        (to_unit_f64(sobol_0(index)) + self.rotation(dim)).fract()
+    }
+
+    fn get_2d(&mut self) -> [f64; 2] {
+        let dim = self.state.dim;
+        self.state.dim += 2;
+        let index = self.shuffled_index(dim);
+        [
+            (to_unit_f64(sobol_0(index)) + self.rotation(dim)).fract(),
+            (to_unit_f64(sobol_1(index)) + self.rotation(dim + 1)).fract(),
+        ]
+    }
+}
+
+fn to_unit_f64(v: u32) -> f64 {
+    v as f64/4294967296_f64
+}
+
+//first sobol dimension, the base 2 van der corput sequence
+fn sobol_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+//second sobol dimension, generated by the primitive polynomial x + 1
+fn sobol_1(mut index: u32) -> u32 {
+    let mut v = 1_u32 << 31;
+    let mut result = 0;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= v;
+        }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+//nested uniform (owen) scrambling: each bit is flipped based on a hash of all the bits above it
+fn owen_scramble(mut v: u32, seed: u32) -> u32 {
+    if seed & 1 != 0 {
+        v ^= 1 << 31;
+    }
+    for b in 1..32 {
+        let mask = u32::MAX << (32 - b);
+        if (mix_bits(((v & mask) ^ seed) as u64) as u32) & (1 << b) != 0 {
+            v ^= 1 << (31 - b);
+        }
+    }
+    v
+}
+
+//radical inverse of `a` in `base`, with the digits at each level permuted by a hash of the
+//digits before them
+fn owen_scrambled_radical_inverse(base: u32, mut a: u64, h: u64) -> f64 {
+    let inv_base = 1_f64/base as f64;
+    let mut inv_base_m = 1_f64;
+    let mut reversed = 0_u64;
+    //keep adding digits until they fall below f64 precision. large bases get there through
+    //`reversed`, which must stay within the 53 bits an f64 holds exactly (and so within u64)
+    while 1_f64 - (base - 1) as f64*inv_base_m < 1_f64 && reversed < (1_u64 << 53)/base as u64 {
+        let next = a/base as u64;
+        let digit = (a - next*base as u64) as u32;
+        let digit_hash = mix_bits(h ^ reversed);
+        let digit = permutation_element(digit, base, digit_hash as u32);
+        reversed = reversed*base as u64 + digit as u64;
+        inv_base_m *= inv_base;
+        a = next;
+    }
+    (inv_base_m*reversed as f64).min(1_f64 - f64::EPSILON)
+}
+
+//element `i` of a random permutation of 0..len picked by `p`, without building the permutation
+//(Kensler 2013, "Correlated Multi-Jittered Sampling")
+fn permutation_element(mut i: u32, len: u32, p: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    ((i as u64 + p as u64) % len as u64) as u32
+}
+
+//tileable blue noise mask of values in (0, 1), built once on first use
+fn blue_noise_mask() -> &'static [f64] {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    MASK.get_or_init(|| void_and_cluster(BLUE_NOISE_SIZE, 1.5, 0x5eed))
+}
+
+//ulichney's void and cluster method: ranks every cell of a toroidal grid so that the cells of
+//rank below any threshold are spread as evenly as possible
+fn void_and_cluster(size: usize, sigma: f64, seed: u64) -> Vec<f64> {
+    let n = size*size;
+
+    //gaussian falloff by toroidal offset, so energy updates are a table lookup
+    let mut kernel = vec![0_f64; n];
+    for dy in 0..size {
+        for dx in 0..size {
+            let wx = dx.min(size - dx) as f64;
+            let wy = dy.min(size - dy) as f64;
+            kernel[dy*size + dx] = (-(wx*wx + wy*wy)/(2_f64*sigma*sigma)).exp();
+        }
+    }
+    let update = |energy: &mut [f64], cell: usize, sign: f64| {
+        let (cx, cy) = (cell % size, cell/size);
+        for y in 0..size {
+            let dy = (y + size - cy) % size;
+            for x in 0..size {
+                let dx = (x + size - cx) % size;
+                energy[y*size + x] += sign*kernel[dy*size + dx];
+            }
+        }
+    };
+    let tightest_cluster = |pattern: &[bool], energy: &[f64]| {
+        (0..n).filter(|&i| pattern[i]).max_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+    };
+    let largest_void = |pattern: &[bool], energy: &[f64]| {
+        (0..n).filter(|&i| !pattern[i]).min_by(|&a, &b| energy[a].total_cmp(&energy[b])).unwrap()
+    };
+
+    //random initial pattern covering about a tenth of the cells
+    let mut rng = Rng::new(seed);
+    let mut pattern = vec![false; n];
+    let mut energy = vec![0_f64; n];
+    let initial = (n/10).max(1);
+    let mut placed = 0;
+    while placed < initial {
+        let cell = (random_f64(&mut rng)*n as f64) as usize % n;
+        if !pattern[cell] {
+            pattern[cell] = true;
+            update(&mut energy, cell, 1_f64);
+            placed += 1;
+        }
+    }
+
+    //relax it by moving the point in the tightest cluster to the largest void until stable
+    loop {
+        let cluster = tightest_cluster(&pattern, &energy);
+        pattern[cluster] = false;
+        update(&mut energy, cluster, -1_f64);
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        update(&mut energy, void, 1_f64);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0_usize; n];
+
+    //ranks below the initial count: take points away from the tightest clusters
+    let mut shrinking = pattern.clone();
+    let mut shrinking_energy = energy.clone();
+    for rank in (0..initial).rev() {
+        let cluster = tightest_cluster(&shrinking, &shrinking_energy);
+        shrinking[cluster] = false;
+        update(&mut shrinking_energy, cluster, -1_f64);
+        ranks[cluster] = rank;
+    }
+
+    //ranks above it: keep filling the largest void
+    for rank in initial..n {
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        update(&mut energy, void, 1_f64);
+        ranks[void] = rank;
+    }
+
+    ranks.into_iter().map(|rank| (rank as f64 + 0.5)/n as f64).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [SamplerKind; 5] = [SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol, SamplerKind::BlueNoise];
+
+    #[test]
+    fn samples_are_in_the_unit_interval() {
+        for kind in ALL {
+            let mut sampler = kind.create(42, 16);
+            for index in (0..200).chain([1000, 65535, 1 << 20, u32::MAX]) {
+                sampler.start_pixel_sample(index as i32 % 7 - 3, 5, index);
+                for dim in 0..64 {
+                    sampler.set_dimension(dim);
+                    let [u, v] = sampler.get_2d();
+                    for value in [sampler.get_1d(), u, v] {
+                        assert!(value.is_finite() && (0_f64..1_f64).contains(&value), "{} sampler gave {} at index {}, dimension {}", kind, value, index, dim);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn large_halton_bases_stay_stratified() {
+        //the first `base` samples of a radical inverse fall into distinct 1/base strata
+        for dim in [12, 30, 63] {
+            let base = PRIMES[dim];
+            let mut sampler = HaltonSampler::new(7);
+            let mut strata: Vec<u32> = (0..base).map(|index| {
+                sampler.start_pixel_sample(0, 0, index);
+                sampler.set_dimension(dim as u32);
+                (sampler.get_1d()*base as f64) as u32
+            }).collect();
+            strata.sort_unstable();
+            assert_eq!(strata, (0..base).collect::<Vec<_>>(), "base {}", base);
+        }
+    }
+
+    #[test]
+    fn names_and_ids_round_trip() {
+        for kind in ALL {
+            assert_eq!(kind.to_string().parse::<SamplerKind>(), Ok(kind));
+            assert_eq!(SamplerKind::from_id(kind.id()), Some(kind));
+        }
+        assert_eq!(SamplerKind::from_id(ALL.len() as u64), None);
+    }
+}
@@ -1,6 +1,6 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
-use crate::{interval::Interval, ray::{Scatter, SetFaceNormal}, vec3::{dot, Point3}};
+use crate::{aabb::Aabb, interval::Interval, onb::Onb, ray::{Scatter, SetFaceNormal}, helper::{INFINITY, PI}, vec3::{dot, random_unit_vector, unit_vector, Point3, Vec3}};
 use crate::ray::Hittable;
 use crate::ray::HitRecord;
 use crate::ray::Ray;
@@ -8,15 +8,19 @@ use crate::ray::Ray;
 pub struct Sphere { 
     center: Point3,
     radius: f64,
-    mat : Option<Rc<dyn Scatter>>,
+    mat : Option<Arc<dyn Scatter>>,
+    bbox : Aabb,
 }
 
 impl Sphere {
-    pub fn new(_center: Point3, _radius: f64, material: Rc<dyn Scatter>) -> Sphere {
+    pub fn new(_center: Point3, _radius: f64, material: Arc<dyn Scatter>) -> Sphere {
+        let radius = _radius.max(0_f64);
+        let rvec = Vec3::new(radius, radius, radius);
         Sphere {
             center: _center,
-            radius: _radius.max(0_f64),
+            radius,
             mat : Some(material),
+            bbox : Aabb::from_points(_center - rvec, _center + rvec),
         }
     }
 }
@@ -45,9 +49,65 @@ impl Hittable for Sphere {
                 rec.p = ray.at(rec.t);
                 let outward_normal = (rec.p - self.center) / self.radius;
                 rec.set_face_normal(ray, &outward_normal);
+                (rec.u, rec.v) = sphere_uv(&outward_normal);
                 rec.mat = self.mat.clone();
                 true
             }
         }
     }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn is_light(&self) -> bool {
+        self.mat.as_ref().is_some_and(|mat| mat.is_emissive())
+    }
+
+    //from outside, uniformly over the cone of directions the sphere covers. from inside every
+    //direction hits, so pick a uniform point on the surface instead
+    fn sample_direction(&self, origin: &Point3, u: [f64; 2]) -> Vec3 {
+        let to_center = self.center - *origin;
+        let dist_squared = to_center.get_len_squared();
+        if dist_squared <= self.radius*self.radius {
+            return self.center + self.radius*random_unit_vector(u) - *origin;
+        }
+        let one_minus_cos_max = self.one_minus_cos_theta_max(dist_squared);
+        let z = 1_f64 - u[0]*one_minus_cos_max;
+        let phi = 2_f64*PI*u[1];
+        let r = (1_f64 - z*z).max(0_f64).sqrt();
+        Onb::new(&to_center).transform(&Vec3::new(r*phi.cos(), r*phi.sin(), z))
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        let mut rec = HitRecord::new_empty();
+        if !self.hit(&Ray::new(*origin, *direction), Interval::new(0.001, INFINITY), &mut rec) {
+            return 0_f64;
+        }
+        let dist_squared = (self.center - *origin).get_len_squared();
+        if dist_squared <= self.radius*self.radius {
+            //area density 1/(4 pi r^2) converted to solid angle
+            let hit_dist_squared = (rec.p - *origin).get_len_squared();
+            let cos = dot(&unit_vector(direction), &rec.normal).abs();
+            return hit_dist_squared/(cos*4_f64*PI*self.radius*self.radius);
+        }
+        1_f64/(2_f64*PI*self.one_minus_cos_theta_max(dist_squared))
+    }
+}
+
+impl Sphere {
+    //1 - cos of the half angle of the cone the sphere subtends, written so it doesn't cancel
+    //out to zero for small or distant spheres
+    fn one_minus_cos_theta_max(&self, dist_squared: f64) -> f64 {
+        let sin_squared = self.radius*self.radius/dist_squared;
+        sin_squared/(1_f64 + (1_f64 - sin_squared).max(0_f64).sqrt())
+    }
+}
+
+//maps a point on the unit sphere to (u, v), with u going around the y axis starting from -x
+//and v going from the bottom pole at y = -1 to the top pole at y = 1
+fn sphere_uv(p: &Point3) -> (f64, f64) {
+    let theta = (-p.y()).clamp(-1_f64, 1_f64).acos();
+    let phi = (-p.z()).atan2(p.x()) + PI;
+    (phi/(2_f64*PI), theta/PI)
 }
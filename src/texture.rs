@@ -0,0 +1,135 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::{color::Color, colorspace::ColorSpace, image::{Image, WrapMode}, input::{read_image, ImageError}, vec3::Point3};
+
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
+}
+
+pub struct SolidColor {
+    albedo: Color,
+}
+
+impl SolidColor {
+    pub fn new(albedo: Color) -> Self {
+        Self {
+            albedo,
+        }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _: f64, _: f64, _: &Point3) -> Color {
+        self.albedo
+    }
+}
+
+//checkerboard of unit cells (times `scale`) in world space, independent of the surface uvs
+pub struct CheckerTexture {
+    inv_scale: f64,
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+}
+
+impl CheckerTexture {
+    pub fn new(scale: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
+        Self {
+            inv_scale: 1_f64/scale,
+            even,
+            odd,
+        }
+    }
+
+    pub fn from_colors(scale: f64, even: Color, odd: Color) -> Self {
+        Self::new(scale, Arc::new(SolidColor::new(even)), Arc::new(SolidColor::new(odd)))
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let x = (self.inv_scale*p.x()).floor() as i64;
+        let y = (self.inv_scale*p.y()).floor() as i64;
+        let z = (self.inv_scale*p.z()).floor() as i64;
+        match (x + y + z).rem_euclid(2) {
+            0 => self.even.value(u, v, p),
+            _ => self.odd.value(u, v, p),
+        }
+    }
+}
+
+//checkerboard in surface uv space with `columns` by `rows` cells over [0,1]x[0,1]
+pub struct UvCheckerTexture {
+    columns: f64,
+    rows: f64,
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+}
+
+impl UvCheckerTexture {
+    pub fn new(columns: f64, rows: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
+        Self {
+            columns,
+            rows,
+            even,
+            odd,
+        }
+    }
+
+    pub fn from_colors(columns: f64, rows: f64, even: Color, odd: Color) -> Self {
+        Self::new(columns, rows, Arc::new(SolidColor::new(even)), Arc::new(SolidColor::new(odd)))
+    }
+}
+
+impl Texture for UvCheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
+        let x = (u*self.columns).floor() as i64;
+        let y = (v*self.rows).floor() as i64;
+        match (x + y).rem_euclid(2) {
+            0 => self.even.value(u, v, p),
+            _ => self.odd.value(u, v, p),
+        }
+    }
+}
+
+//bilinearly filtered image lookup in surface uv space, with v = 0 at the bottom row
+pub struct ImageTexture {
+    image: Image,
+    pub wrap_u: WrapMode,
+    pub wrap_v: WrapMode,
+}
+
+impl ImageTexture {
+    //`image` must hold linear colors
+    pub fn new(image: Image) -> Self {
+        Self {
+            image,
+            wrap_u: WrapMode::Repeat,
+            wrap_v: WrapMode::Repeat,
+        }
+    }
+
+    //png, jpeg and ppm files are decoded from srgb, hdr and pfm files are taken as linear. the
+    //colors are assumed to have srgb primaries and get converted into the `working` space
+    pub fn load(path: impl AsRef<Path>, working: ColorSpace) -> Result<Self, ImageError> {
+        Self::load_in(path, ColorSpace::LinearSrgb, working)
+    }
+
+    //like load, for a file whose colors use the primaries of `space`
+    pub fn load_in(path: impl AsRef<Path>, space: ColorSpace, working: ColorSpace) -> Result<Self, ImageError> {
+        let mut image = read_image(path)?;
+        space.convert_image(&mut image, working);
+        Ok(Self::new(image))
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _: &Point3) -> Color {
+        if self.image.width() == 0 || self.image.height() == 0 {
+            return Color::new(0_f64, 1_f64, 1_f64);
+        }
+        let x = u*self.image.width() as f64;
+        let y = (1_f64 - v)*self.image.height() as f64;
+        self.image.sample_bilinear(x, y, self.wrap_u, self.wrap_v)
+    }
+}
@@ -0,0 +1,180 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{color::{luminance, Color}, colorspace::Mat3, image::Image};
+
+//operators compressing scene radiance into the [0, 1] range of a display
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ToneMap {
+    Clamp, //no compression, everything above 1 clips
+    Reinhard, //l/(1 + l) on luminance, approaches white but never reaches it
+    ReinhardExtended, //reinhard that maps the white point to 1
+    Aces, //stephen hill's fit of the aces reference and sdr output transforms
+    Hable, //john hable's filmic curve from uncharted 2
+    AgX, //troy sobotka's agx, desaturating bright colors towards white instead of skewing their hue
+}
+
+impl ToneMap {
+    //maps one exposed, scene linear color to display linear values in [0, 1]
+    pub fn apply(&self, color: &Color, white_point: f64) -> Color {
+        match self {
+            ToneMap::Clamp => *color,
+            ToneMap::Reinhard => scale_luminance(color, |l| l/(1_f64 + l)),
+            ToneMap::ReinhardExtended => {
+                let white_squared = white_point*white_point;
+                scale_luminance(color, |l| l*(1_f64 + l/white_squared)/(1_f64 + l))
+            }
+            ToneMap::Aces => {
+                let v = ACES_INPUT.transform(color);
+                ACES_OUTPUT.transform(&map(&v, rrt_and_odt_fit))
+            }
+            ToneMap::Hable => {
+                let scale = 1_f64/hable_partial(HABLE_WHITE);
+                map(color, |c| hable_partial(HABLE_EXPOSURE_BIAS*c)*scale)
+            }
+            ToneMap::AgX => {
+                let v = map(&AGX_INPUT.transform(color), |c| {
+                    let log = c.max(1e-10).log2().clamp(AGX_MIN_EV, AGX_MAX_EV);
+                    agx_contrast((log - AGX_MIN_EV)/(AGX_MAX_EV - AGX_MIN_EV))
+                });
+                //the agx curve ends in a 2.2 gamma encoding, undone here so the output stays linear
+                map(&AGX_OUTPUT.transform(&v), |c| c.max(0_f64).powf(2.2))
+            }
+        }
+    }
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" | "none" => Ok(ToneMap::Clamp),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "reinhard-extended" => Ok(ToneMap::ReinhardExtended),
+            "aces" => Ok(ToneMap::Aces),
+            "hable" | "filmic" => Ok(ToneMap::Hable),
+            "agx" => Ok(ToneMap::AgX),
+            _ => Err(format!("unknown tone mapping operator '{}', expected clamp, reinhard, reinhard-extended, aces, hable or agx", s)),
+        }
+    }
+}
+
+impl fmt::Display for ToneMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ToneMap::Clamp => "clamp",
+            ToneMap::Reinhard => "reinhard",
+            ToneMap::ReinhardExtended => "reinhard-extended",
+            ToneMap::Aces => "aces",
+            ToneMap::Hable => "hable",
+            ToneMap::AgX => "agx",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+//everything between the rendered radiance and the transfer function of a display: exposure,
+//then tone mapping, then clipping to [0, 1]
+#[derive(Clone, Copy, Debug)]
+pub struct DisplayTransform {
+    pub exposure: f64, //in stops, each one doubling the brightness
+    pub tone_map: ToneMap,
+    pub white_point: f64, //smallest luminance extended reinhard maps to white
+}
+
+impl DisplayTransform {
+    pub fn new() -> Self {
+        Self {
+            exposure: 0_f64,
+            tone_map: ToneMap::Clamp,
+            white_point: 4_f64,
+        }
+    }
+
+    pub fn apply(&self, color: &Color) -> Color {
+        let exposed = 2_f64.powf(self.exposure)*(*color);
+        //stray nan or negative samples would otherwise spread to other channels through the matrices
+        let exposed = map(&exposed, |c| if c.is_finite() { c.max(0_f64) } else { 0_f64 });
+        map(&self.tone_map.apply(&exposed, self.white_point), |c| c.clamp(0_f64, 1_f64))
+    }
+
+    pub fn apply_image(&self, image: &Image) -> Image {
+        let mut out = image.clone();
+        for pixel in out.pixels_mut() {
+            *pixel = self.apply(pixel);
+        }
+        out
+    }
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+//rescales the color so its luminance goes through `curve`, keeping its hue and saturation
+fn scale_luminance(color: &Color, curve: impl Fn(f64) -> f64) -> Color {
+    let l = luminance(color);
+    match l > 0_f64 {
+        true => (curve(l)/l)*(*color),
+        false => Color::new_empty(),
+    }
+}
+
+fn map(color: &Color, f: impl Fn(f64) -> f64) -> Color {
+    Color::new(f(color.x()), f(color.y()), f(color.z()))
+}
+
+//rec.709 to aces ap1 with the saturation tweak of the rrt folded in, and back to rec.709
+const ACES_INPUT: Mat3 = Mat3([
+    [0.59719, 0.35458, 0.04823],
+    [0.07600, 0.90834, 0.01566],
+    [0.02840, 0.13383, 0.83777],
+]);
+
+const ACES_OUTPUT: Mat3 = Mat3([
+    [1.60475, -0.53108, -0.07367],
+    [-0.10208, 1.10813, -0.00605],
+    [-0.00327, -0.07276, 1.07602],
+]);
+
+fn rrt_and_odt_fit(v: f64) -> f64 {
+    let a = v*(v + 0.0245786) - 0.000090537;
+    let b = v*(0.983729*v + 0.4329510) + 0.238081;
+    a/b
+}
+
+const HABLE_EXPOSURE_BIAS: f64 = 2_f64;
+//linear value that ends up as white
+const HABLE_WHITE: f64 = 11.2;
+
+fn hable_partial(x: f64) -> f64 {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    (x*(a*x + c*b) + d*e)/(x*(a*x + b) + d*f) - e/f
+}
+
+//rec.709 into agx's inset primaries and back out again
+const AGX_INPUT: Mat3 = Mat3([
+    [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+    [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+    [0.0423756549057051, 0.0784336, 0.879142973793104],
+]);
+
+const AGX_OUTPUT: Mat3 = Mat3([
+    [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+    [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+    [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+]);
+
+//range of stops around middle grey the agx log encoding covers
+const AGX_MIN_EV: f64 = -12.47393;
+const AGX_MAX_EV: f64 = 4.026069;
+
+//polynomial fit of the default agx contrast curve
+fn agx_contrast(x: f64) -> f64 {
+    let x2 = x*x;
+    let x4 = x2*x2;
+    15.5*x4*x2 - 40.14*x4*x + 31.96*x4 - 6.868*x2*x + 0.4298*x2 + 0.1191*x - 0.00232
+}
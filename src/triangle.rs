@@ -0,0 +1,280 @@
+use std::sync::Arc;
+
+use crate::{aabb::Aabb, helper::INFINITY, interval::Interval, ray::{HitRecord, Hittable, Ray, Scatter, SetFaceNormal}, vec3::{cross, dot, unit_vector, Point3, Vec3}};
+
+pub struct Triangle {
+    v : [Point3; 3],
+    mat : Option<Arc<dyn Scatter>>,
+    bbox : Aabb,
+}
+
+impl Triangle {
+    pub fn new(v0: Point3, v1: Point3, v2: Point3, material: Arc<dyn Scatter>) -> Self {
+        Self {
+            v : [v0, v1, v2],
+            mat : Some(material),
+            bbox : triangle_bbox(&[v0, v1, v2]),
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray :&Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let Some((t, bary)) = intersect(ray, ray_t, &self.v) else {
+            return false;
+        };
+        rec.t = t;
+        rec.p = ray.at(t);
+        rec.bary = bary;
+        (rec.u, rec.v) = (bary.y(), bary.z());
+        rec.set_face_normal(ray, &geometric_normal(&self.v));
+        rec.mat = self.mat.clone();
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    fn is_light(&self) -> bool {
+        self.mat.as_ref().is_some_and(|mat| mat.is_emissive())
+    }
+
+    fn sample_direction(&self, origin: &Point3, u: [f64; 2]) -> Vec3 {
+        sample_triangle(&self.v, u) - *origin
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        triangle_pdf(&self.v, origin, direction)
+    }
+}
+
+//vertex attribute indices of one mesh face, normals and uvs are optional per face
+#[derive(Clone, Copy, Debug)]
+pub struct MeshFace {
+    pub positions : [u32; 3],
+    pub normals : Option<[u32; 3]>,
+    pub uvs : Option<[u32; 3]>,
+}
+
+impl MeshFace {
+    pub fn new(positions: [u32; 3]) -> Self {
+        Self {
+            positions,
+            normals : None,
+            uvs : None,
+        }
+    }
+}
+
+//triangle mesh sharing one set of vertex attribute arrays between all of its faces
+pub struct TriangleMesh {
+    positions : Vec<Point3>,
+    normals : Vec<Vec3>,
+    uvs : Vec<[f64; 2]>,
+    faces : Vec<MeshFace>,
+    mat : Option<Arc<dyn Scatter>>,
+}
+
+impl TriangleMesh {
+    //panics if a face refers to a vertex attribute the mesh doesn't have, which would otherwise
+    //only show up as an out of bounds index once a ray hits that face
+    pub fn new(positions: Vec<Point3>, normals: Vec<Vec3>, uvs: Vec<[f64; 2]>, faces: Vec<MeshFace>, material: Arc<dyn Scatter>) -> Self {
+        for (i, face) in faces.iter().enumerate() {
+            check_indices(i, "position", Some(face.positions), positions.len());
+            check_indices(i, "normal", face.normals, normals.len());
+            check_indices(i, "uv", face.uvs, uvs.len());
+        }
+        Self {
+            positions,
+            normals,
+            uvs,
+            faces,
+            mat : Some(material),
+        }
+    }
+
+    //mesh with positions only, shaded with flat face normals
+    pub fn from_indices(positions: Vec<Point3>, indices: Vec<[u32; 3]>, material: Arc<dyn Scatter>) -> Self {
+        let faces = indices.into_iter().map(MeshFace::new).collect();
+        Self::new(positions, Vec::new(), Vec::new(), faces, material)
+    }
+
+    pub fn positions(&self) -> &[Point3] {
+        &self.positions
+    }
+
+    pub fn normals(&self) -> &[Vec3] {
+        &self.normals
+    }
+
+    pub fn uvs(&self) -> &[[f64; 2]] {
+        &self.uvs
+    }
+
+    pub fn faces(&self) -> &[MeshFace] {
+        &self.faces
+    }
+
+    //one hittable per face, for adding to a HittableList or Bvh
+    pub fn triangles(self: &Arc<Self>) -> Vec<Arc<dyn Hittable>> {
+        (0..self.faces.len())
+            .map(|face| Arc::new(MeshTriangle { mesh : self.clone(), face }) as Arc<dyn Hittable>)
+            .collect()
+    }
+
+    fn vertices(&self, face: usize) -> [Point3; 3] {
+        self.faces[face].positions.map(|i| self.positions[i as usize])
+    }
+}
+
+fn check_indices(face: usize, what: &str, indices: Option<[u32; 3]>, count: usize) {
+    if let Some(indices) = indices
+        && let Some(&i) = indices.iter().find(|&&i| i as usize >= count) {
+        panic!("mesh face {} uses {} index {} but the mesh has only {} of them", face, what, i, count);
+    }
+}
+
+//a single face of a TriangleMesh
+pub struct MeshTriangle {
+    mesh : Arc<TriangleMesh>,
+    face : usize,
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, ray :&Ray, ray_t: Interval, rec: &mut HitRecord) -> bool {
+        let v = self.mesh.vertices(self.face);
+        let Some((t, bary)) = intersect(ray, ray_t, &v) else {
+            return false;
+        };
+        rec.t = t;
+        rec.p = ray.at(t);
+        rec.bary = bary;
+        rec.set_face_normal(ray, &geometric_normal(&v));
+
+        let face = &self.mesh.faces[self.face];
+        (rec.u, rec.v) = match face.uvs {
+            Some(ti) => {
+                let t = ti.map(|i| self.mesh.uvs[i as usize]);
+                (
+                    bary.x()*t[0][0] + bary.y()*t[1][0] + bary.z()*t[2][0],
+                    bary.x()*t[0][1] + bary.y()*t[1][1] + bary.z()*t[2][1],
+                )
+            }
+            None => (bary.y(), bary.z()),
+        };
+
+        //interpolate the vertex normals, kept on the same side of the surface as the geometric normal
+        if let Some(ni) = face.normals {
+            let n = ni.map(|i| self.mesh.normals[i as usize]);
+            let shading_normal = bary.x()*n[0] + bary.y()*n[1] + bary.z()*n[2];
+            if !shading_normal.near_zero() {
+                let shading_normal = unit_vector(&shading_normal);
+                rec.normal = if dot(&shading_normal, &rec.normal) < 0_f64 { -shading_normal } else { shading_normal };
+            }
+        }
+        rec.mat = self.mesh.mat.clone();
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        triangle_bbox(&self.mesh.vertices(self.face))
+    }
+
+    fn is_light(&self) -> bool {
+        self.mesh.mat.as_ref().is_some_and(|mat| mat.is_emissive())
+    }
+
+    fn sample_direction(&self, origin: &Point3, u: [f64; 2]) -> Vec3 {
+        sample_triangle(&self.mesh.vertices(self.face), u) - *origin
+    }
+
+    fn pdf_value(&self, origin: &Point3, direction: &Vec3) -> f64 {
+        triangle_pdf(&self.mesh.vertices(self.face), origin, direction)
+    }
+}
+
+fn triangle_bbox(v: &[Point3; 3]) -> Aabb {
+    Aabb::surrounding(&Aabb::from_points(v[0], v[1]), &Aabb::from_points(v[2], v[2]))
+}
+
+fn geometric_normal(v: &[Point3; 3]) -> Vec3 {
+    unit_vector(&cross(&(v[1] - v[0]), &(v[2] - v[0])))
+}
+
+//uniformly distributed point on the triangle
+fn sample_triangle(v: &[Point3; 3], u: [f64; 2]) -> Point3 {
+    let su0 = u[0].sqrt();
+    let b0 = 1_f64 - su0;
+    let b1 = u[1]*su0;
+    b0*v[0] + b1*v[1] + (1_f64 - b0 - b1)*v[2]
+}
+
+//area density 1/area of sample_triangle converted to solid angle as seen from `origin`
+fn triangle_pdf(v: &[Point3; 3], origin: &Point3, direction: &Vec3) -> f64 {
+    let ray = Ray::new(*origin, *direction);
+    let Some((t, _)) = intersect(&ray, Interval::new(0.001, INFINITY), v) else {
+        return 0_f64;
+    };
+    let n = cross(&(v[1] - v[0]), &(v[2] - v[0]));
+    let area = 0.5*n.get_len();
+    let dist_squared = t*t*direction.get_len_squared();
+    let cos = dot(&unit_vector(direction), &unit_vector(&n)).abs();
+    if cos <= 0_f64 || area <= 0_f64 {
+        return 0_f64;
+    }
+    dist_squared/(cos*area)
+}
+
+//watertight ray/triangle test (Woop, Benthin and Wald 2013). the ray is sheared so that it
+//points down +z from the origin, which makes edges shared between triangles give consistent
+//results and closes the cracks the usual Moller-Trumbore test leaves along them.
+//returns the ray parameter and the barycentric weights of v0, v1, v2
+fn intersect(ray: &Ray, ray_t: Interval, p: &[Point3; 3]) -> Option<(f64, Vec3)> {
+    let dir = ray.dir();
+    let abs_dir = [dir.x().abs(), dir.y().abs(), dir.z().abs()];
+    let kz = match (abs_dir[0] > abs_dir[1], abs_dir[0] > abs_dir[2], abs_dir[1] > abs_dir[2]) {
+        (true, true, _) => 0,
+        (false, _, true) => 1,
+        _ => 2,
+    };
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    //preserve the winding direction of the triangle
+    if dir.get(kz) < 0_f64 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    let sx = dir.get(kx)/dir.get(kz);
+    let sy = dir.get(ky)/dir.get(kz);
+    let sz = 1_f64/dir.get(kz);
+
+    let a = p[0] - ray.origin();
+    let b = p[1] - ray.origin();
+    let c = p[2] - ray.origin();
+    let ax = a.get(kx) - sx*a.get(kz);
+    let ay = a.get(ky) - sy*a.get(kz);
+    let bx = b.get(kx) - sx*b.get(kz);
+    let by = b.get(ky) - sy*b.get(kz);
+    let cx = c.get(kx) - sx*c.get(kz);
+    let cy = c.get(ky) - sy*c.get(kz);
+
+    //scaled barycentrics from the 2d edge functions
+    let u = cx*by - cy*bx;
+    let v = ax*cy - ay*cx;
+    let w = bx*ay - by*ax;
+    if (u < 0_f64 || v < 0_f64 || w < 0_f64) && (u > 0_f64 || v > 0_f64 || w > 0_f64) {
+        return None;
+    }
+    let det = u + v + w;
+    if det == 0_f64 {
+        return None;
+    }
+
+    let scaled_t = u*sz*a.get(kz) + v*sz*b.get(kz) + w*sz*c.get(kz);
+    let t = scaled_t/det;
+    if !ray_t.surrounds(t) {
+        return None;
+    }
+    Some((t, Vec3::new(u/det, v/det, w/det)))
+}
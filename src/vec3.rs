@@ -9,6 +9,8 @@ use std::ops::Div;
 
 use crate::helper::random_f64;
 use crate::helper::random_f64_range;
+use crate::helper::Rng;
+use crate::helper::PI;
 
 #[derive(Copy, Clone, Debug)]
 pub struct Vec3 {
@@ -60,12 +62,12 @@ impl Vec3 {
         self.e[0].abs() < s && self.e[1].abs() < s && self.e[2].abs() < s
     }
 
-    pub fn random() -> Self {
-        Self::new(random_f64(), random_f64(), random_f64())
+    pub fn random(rng: &mut Rng) -> Self {
+        Self::new(random_f64(rng), random_f64(rng), random_f64(rng))
     }
 
-    pub fn random_range(min: f64, max: f64) -> Self {
-        Self::new(random_f64_range(min, max), random_f64_range(min, max), random_f64_range(min, max))
+    pub fn random_range(rng: &mut Rng, min: f64, max: f64) -> Self {
+        Self::new(random_f64_range(rng, min, max), random_f64_range(rng, min, max), random_f64_range(rng, min, max))
     }
 }
 
@@ -182,14 +184,12 @@ pub fn unit_vector(u: &Vec3) -> Vec3 {
     *u / u.get_len()
 }
 
-pub fn random_unit_vector() -> Vec3 {
-    loop {
-        let p = Vec3::random_range(-1_f64, 1_f64);
-        let lensq = p.get_len_squared();
-        if lensq <= 1_f64  && 1e-160_f64 < lensq {
-            return p / lensq.sqrt();
-        }
-    }
+//maps a uniform sample in [0,1)^2 to a uniformly distributed direction on the unit sphere
+pub fn random_unit_vector(u: [f64; 2]) -> Vec3 {
+    let z = 1_f64 - 2_f64*u[0];
+    let r = (1_f64 - z*z).max(0_f64).sqrt();
+    let phi = 2_f64*PI*u[1];
+    Vec3::new(r*phi.cos(), r*phi.sin(), z)
 }
 
 pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
@@ -203,9 +203,26 @@ pub fn refract(uv: &Vec3, n: &Vec3, etai_over_etat: f64) -> Vec3 {
     r_out_perpen + r_out_parallel
 }
 
-pub fn random_in_unit_disk() -> Vec3 {
-    loop {
-        let p = Vec3::new(random_f64_range(-1_f64, 1_f64), random_f64_range(-1_f64, 1_f64), 0_f64);
-        if p.get_len_squared() < 1_f64 { return p};
-    }
+//maps a uniform sample in [0,1)^2 to the unit disk with shirley's concentric mapping, which keeps
+//stratified samples stratified
+pub fn random_in_unit_disk(u: [f64; 2]) -> Vec3 {
+    let a = 2_f64*u[0] - 1_f64;
+    let b = 2_f64*u[1] - 1_f64;
+    if a == 0_f64 && b == 0_f64 {
+        return Vec3::new_empty();
+    }
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI/4_f64*(b/a))
+    } else {
+        (b, PI/2_f64 - PI/4_f64*(a/b))
+    };
+    Vec3::new(r*theta.cos(), r*theta.sin(), 0_f64)
+}
+
+//cosine weighted direction on the +z hemisphere via malley's method (project the disk up), so the
+//pdf is cos(theta)/pi
+pub fn random_cosine_direction(u: [f64; 2]) -> Vec3 {
+    let d = random_in_unit_disk(u);
+    let z = (1_f64 - d.x()*d.x() - d.y()*d.y()).max(0_f64).sqrt();
+    Vec3::new(d.x(), d.y(), z)
 }